@@ -0,0 +1,679 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, AttributeArgs, Error, Expr, FnArg, GenericArgument,
+    ImplItem, Item, ItemFn, ItemImpl, Lit, Meta, NestedMeta, Pat, PathArguments, ReturnType, Type,
+};
+
+/// Turns a function or an `impl Op` block into a bifrost op.
+///
+/// Applied to a function, a struct named after the function (in pascal case) is generated with
+/// one public field per argument, along with an `Op` implementation whose `execute` calls the
+/// function. The struct derives `Debug`, `Serialize`, `Deserialize` and `schemars::JsonSchema`,
+/// which the types of the arguments must therefore implement. A function returning `Result<T, E>` becomes an op with
+/// `Output = T` and `Error = E`, any other function becomes an infallible op:
+///
+/// ```ignore
+/// #[bifrost::op]
+/// fn greet(name: String) -> String {
+///     format!("Hi there, {}", name)
+/// }
+///
+/// let op = Greet { name: String::from("Bifrost") };
+/// ```
+///
+/// Applied to an `impl Op for T` (or `impl StreamingOp for T`) block, `ID` is filled in if missing,
+/// `Error` defaults to `bifrost::op::Never` and `execute` is gated behind the `remote` and `debug`
+/// features. Structs are not supported, as the macro cannot tell how they execute: their
+/// `impl Op` block is annotated instead.
+///
+/// The id defaults to the snake case name of the function or type, and can be overridden with
+/// `#[bifrost::op(id = "...")]`. Ops that can safely be executed more than once, and may therefore
+/// be retried by the dispatcher, are marked with `#[bifrost::op(idempotent)]`. Ops whose output
+/// may be cached by the dispatcher declare for how many seconds with
+/// `#[bifrost::op(cache_ttl = 60)]`. Ops belonging to a specific heimdall module, to which
+/// multi-module dispatchers route them, declare it with `#[bifrost::op(module = "...")]`.
+///
+/// Ids can be namespaced, so that ops from several crates can be combined in one guest without
+/// clashing: `#[bifrost::op(namespace = "billing")]` turns the id `charge` into
+/// `billing::charge`, while `namespace(crate)` and `namespace(module)` prefix it with the name of
+/// the crate or the module path of the op. The id is set as `Op::ID`, which `Op::id` returns and
+/// from which `bifrost::entrypoint!` rejects duplicate ids at compile time.
+///
+/// The manifest entry of the op (`Op::MANIFEST`) is filled in from the above and the doc comments
+/// of the op. The schemas of the types of the op are added to it by heimdall, from their
+/// `schemars::JsonSchema` implementations, see `bifrost::entrypoint!`.
+#[proc_macro_attribute]
+pub fn op(args: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as AttributeArgs);
+    let item = parse_macro_input!(item as Item);
+
+    let result = OpArgs::parse(args).and_then(|args| match item {
+        Item::Fn(f) => expand_fn(args, f),
+        Item::Impl(i) => expand_impl(args, i),
+        Item::Struct(s) => Err(Error::new(
+            s.ident.span(),
+            "#[bifrost::op] cannot be applied to structs, apply it to their `impl Op` block instead",
+        )),
+        other => Err(Error::new(
+            other.span(),
+            "#[bifrost::op] can only be applied to functions and `impl Op` blocks",
+        )),
+    });
+
+    match result {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct OpArgs {
+    id: Option<String>,
+    namespace: Option<Namespace>,
+    idempotent: bool,
+    cache_ttl: Option<u64>,
+    module: Option<String>,
+}
+
+/// Prefix of the id of an op.
+enum Namespace {
+    Name(String),
+    Crate,
+    Module,
+}
+
+/// Id of an op, along with its namespace, which may only be known once expanded.
+struct OpId {
+    namespace: Option<Namespace>,
+    id: String,
+}
+
+impl OpArgs {
+    fn parse(args: AttributeArgs) -> Result<Self, Error> {
+        let mut id = None;
+        let mut namespace = None;
+        let mut idempotent = false;
+        let mut cache_ttl = None;
+        let mut module = None;
+
+        for arg in args {
+            match arg {
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("id") => match nv.lit {
+                    Lit::Str(s) => id = Some(s.value()),
+                    lit => return Err(Error::new(lit.span(), "expected a string literal")),
+                },
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("namespace") => {
+                    match nv.lit {
+                        Lit::Str(s) => namespace = Some(Namespace::Name(s.value())),
+                        lit => return Err(Error::new(lit.span(), "expected a string literal")),
+                    }
+                }
+                NestedMeta::Meta(Meta::List(list)) if list.path.is_ident("namespace") => {
+                    let scope = match list.nested.iter().collect::<Vec<_>>().as_slice() {
+                        [NestedMeta::Meta(Meta::Path(p))] if p.is_ident("crate") => {
+                            Namespace::Crate
+                        }
+                        [NestedMeta::Meta(Meta::Path(p))] if p.is_ident("module") => {
+                            Namespace::Module
+                        }
+                        _ => {
+                            return Err(Error::new(
+                                list.span(),
+                                "expected `namespace(crate)` or `namespace(module)`",
+                            ))
+                        }
+                    };
+
+                    namespace = Some(scope);
+                }
+                NestedMeta::Meta(Meta::Path(p)) if p.is_ident("idempotent") => idempotent = true,
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("cache_ttl") => {
+                    match nv.lit {
+                        Lit::Int(i) => cache_ttl = Some(i.base10_parse()?),
+                        lit => return Err(Error::new(lit.span(), "expected a number of seconds")),
+                    }
+                }
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("module") => {
+                    match nv.lit {
+                        Lit::Str(s) => module = Some(s.value()),
+                        lit => return Err(Error::new(lit.span(), "expected a string literal")),
+                    }
+                }
+                other => {
+                    return Err(Error::new(
+                        other.span(),
+                        concat!(
+                            "unsupported argument, expected `id = \"...\"`, `namespace = ...`, ",
+                            "`idempotent`, `cache_ttl = ...` or `module = \"...\"`",
+                        ),
+                    ))
+                }
+            }
+        }
+
+        Ok(OpArgs {
+            id,
+            namespace,
+            idempotent,
+            cache_ttl,
+            module,
+        })
+    }
+}
+
+fn expand_fn(args: OpArgs, f: ItemFn) -> Result<TokenStream2, Error> {
+    let ItemFn {
+        attrs,
+        vis,
+        sig,
+        block,
+    } = f;
+
+    if sig.asyncness.is_some() {
+        return Err(Error::new(sig.span(), "ops cannot be async"));
+    }
+
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new(sig.generics.span(), "ops cannot be generic"));
+    }
+
+    let fn_ident = &sig.ident;
+    let name = format_ident!(
+        "{}",
+        pascal_case(&sig.ident.to_string()),
+        span = sig.ident.span()
+    );
+    let id = OpId {
+        namespace: args.namespace,
+        id: args.id.unwrap_or_else(|| sig.ident.to_string()),
+    };
+    let idempotent = args.idempotent;
+
+    let (output, error, fallible) = match &sig.output {
+        ReturnType::Default => (quote!(()), quote!(::bifrost::op::Never), false),
+        ReturnType::Type(_, ty) => match result_types(ty) {
+            Some((output, error)) => (quote!(#output), quote!(#error), true),
+            None => (quote!(#ty), quote!(::bifrost::op::Never), false),
+        },
+    };
+
+    let mut fields = Vec::new();
+    let mut params = Vec::new();
+    let mut types = Vec::new();
+
+    for input in sig.inputs.iter() {
+        match input {
+            FnArg::Typed(arg) => match &*arg.pat {
+                Pat::Ident(pat) if pat.by_ref.is_none() && pat.subpat.is_none() => {
+                    let ident = &pat.ident;
+                    let mutability = &pat.mutability;
+                    let arg_ty = &arg.ty;
+                    fields.push(ident.clone());
+                    params.push(quote!(#mutability #ident: #arg_ty));
+                    types.push(arg.ty.clone());
+                }
+                other => {
+                    return Err(Error::new(
+                        other.span(),
+                        "op arguments must be plain identifiers",
+                    ))
+                }
+            },
+            FnArg::Receiver(r) => {
+                return Err(Error::new(r.span(), "ops cannot take `self`"));
+            }
+        }
+    }
+
+    let manifest = Manifest {
+        id: &id,
+        streaming: false,
+        idempotent,
+        cache_ttl: args.cache_ttl,
+        module: args.module.as_deref(),
+        description: description(&attrs),
+    }
+    .tokens();
+
+    let cache_ttl = cache_ttl(args.cache_ttl);
+    let module = module(args.module);
+    let id = id.tokens();
+
+    // Docs describe the op, so they go on the struct as well, while other attributes such as
+    // `#[inline]` or `#[allow(...)]` only apply to the function.
+    let docs = attrs.iter().filter(|attr| attr.path.is_ident("doc"));
+
+    let ret = &sig.output;
+    let call = if fallible {
+        quote!(#fn_ident(#( #fields ),*))
+    } else {
+        quote!(::std::result::Result::Ok(#fn_ident(#( #fields ),*)))
+    };
+
+    Ok(quote! {
+        #(#docs)*
+        #[derive(
+            Debug,
+            ::bifrost::__private::serde::Serialize,
+            ::bifrost::__private::serde::Deserialize,
+            ::bifrost::__private::schemars::JsonSchema,
+        )]
+        #[serde(crate = "::bifrost::__private::serde")]
+        #[schemars(crate = "::bifrost::__private::schemars")]
+        #vis struct #name {
+            #( pub #fields: #types, )*
+        }
+
+        impl ::bifrost::op::Op for #name {
+            type Output = #output;
+
+            type Error = #error;
+
+            const IDEMPOTENT: bool = #idempotent;
+
+            const CACHE_TTL: ::std::option::Option<::std::time::Duration> = #cache_ttl;
+
+            const MODULE: ::std::option::Option<&'static str> = #module;
+
+            const MANIFEST: &'static str = #manifest;
+
+            const ID: &'static str = #id;
+
+            #[cfg(any(feature = "remote", feature = "debug"))]
+            fn execute(self) -> ::std::result::Result<Self::Output, Self::Error> {
+                let #name { #( #fields ),* } = self;
+                #call
+            }
+        }
+
+        #(#attrs)*
+        #[cfg(any(feature = "remote", feature = "debug"))]
+        #vis fn #fn_ident(#( #params ),*) #ret #block
+    })
+}
+
+fn expand_impl(args: OpArgs, mut imp: ItemImpl) -> Result<TokenStream2, Error> {
+    if imp.trait_.is_none() {
+        return Err(Error::new(imp.self_ty.span(), "expected `impl Op for ...`"));
+    }
+
+    if let Some(m) = imp.items.iter().find_map(|item| match item {
+        ImplItem::Method(m) if m.sig.ident == "id" => Some(m),
+        _ => None,
+    }) {
+        return Err(Error::new(
+            m.sig.ident.span(),
+            "`fn id()` returns `const ID`, set the id there instead",
+        ));
+    }
+
+    let has_id = imp
+        .items
+        .iter()
+        .any(|item| matches!(item, ImplItem::Const(c) if c.ident == "ID"));
+
+    let id = match (has_id, args.id, args.namespace) {
+        (true, Some(_), _) => {
+            return Err(Error::new(
+                imp.self_ty.span(),
+                "id is given both as an argument and as `const ID`",
+            ))
+        }
+        (true, None, Some(_)) => {
+            return Err(Error::new(
+                imp.self_ty.span(),
+                "namespaces only apply to ids filled in by `#[bifrost::op]`, not to `const ID`",
+            ))
+        }
+        (true, None, None) => literal_id(&imp).map(|id| OpId {
+            namespace: None,
+            id,
+        }),
+        (false, id, namespace) => {
+            let id = OpId {
+                namespace,
+                id: match id {
+                    Some(id) => id,
+                    None => snake_case(&type_name(&imp.self_ty)?),
+                },
+            };
+
+            let tokens = id.tokens();
+
+            imp.items.push(parse_quote! {
+                const ID: &'static str = #tokens;
+            });
+
+            Some(id)
+        }
+    };
+
+    if args.idempotent {
+        let has_idempotent = imp
+            .items
+            .iter()
+            .any(|item| matches!(item, ImplItem::Const(c) if c.ident == "IDEMPOTENT"));
+
+        if has_idempotent {
+            return Err(Error::new(
+                imp.self_ty.span(),
+                "idempotency is given both as an argument and as `const IDEMPOTENT`",
+            ));
+        }
+
+        imp.items.push(parse_quote! {
+            const IDEMPOTENT: bool = true;
+        });
+    }
+
+    if let Some(ttl) = args.cache_ttl {
+        let has_cache_ttl = imp
+            .items
+            .iter()
+            .any(|item| matches!(item, ImplItem::Const(c) if c.ident == "CACHE_TTL"));
+
+        if has_cache_ttl {
+            return Err(Error::new(
+                imp.self_ty.span(),
+                "cache TTL is given both as an argument and as `const CACHE_TTL`",
+            ));
+        }
+
+        let cache_ttl = cache_ttl(Some(ttl));
+
+        imp.items.push(parse_quote! {
+            const CACHE_TTL: ::std::option::Option<::std::time::Duration> = #cache_ttl;
+        });
+    }
+
+    if let Some(id) = args.module.clone() {
+        let has_module = imp
+            .items
+            .iter()
+            .any(|item| matches!(item, ImplItem::Const(c) if c.ident == "MODULE"));
+
+        if has_module {
+            return Err(Error::new(
+                imp.self_ty.span(),
+                "module is given both as an argument and as `const MODULE`",
+            ));
+        }
+
+        let module = module(Some(id));
+
+        imp.items.push(parse_quote! {
+            const MODULE: ::std::option::Option<&'static str> = #module;
+        });
+    }
+
+    let has_error = imp
+        .items
+        .iter()
+        .any(|item| matches!(item, ImplItem::Type(t) if t.ident == "Error"));
+
+    if !has_error {
+        imp.items.push(parse_quote! {
+            type Error = ::bifrost::op::Never;
+        });
+    }
+
+    let has_manifest = imp
+        .items
+        .iter()
+        .any(|item| matches!(item, ImplItem::Const(c) if c.ident == "MANIFEST"));
+
+    // Ops whose id is computed rather than written as a literal are left out of the manifest.
+    if let (Some(id), false) = (id, has_manifest) {
+        let streaming = imp
+            .trait_
+            .as_ref()
+            .and_then(|(_, path, _)| path.segments.last())
+            .is_some_and(|segment| segment.ident == "StreamingOp");
+
+        let manifest = Manifest {
+            id: &id,
+            streaming,
+            idempotent: args.idempotent || const_true(&imp, "IDEMPOTENT"),
+            cache_ttl: args.cache_ttl,
+            module: args.module.as_deref(),
+            description: description(&imp.attrs),
+        }
+        .tokens();
+
+        imp.items.push(parse_quote! {
+            const MANIFEST: &'static str = #manifest;
+        });
+    }
+
+    for item in imp.items.iter_mut() {
+        if let ImplItem::Method(m) = item {
+            if m.sig.ident == "execute" && !m.attrs.iter().any(|a| a.path.is_ident("cfg")) {
+                m.attrs
+                    .push(parse_quote!(#[cfg(any(feature = "remote", feature = "debug"))]));
+            }
+        }
+    }
+
+    Ok(quote!(#imp))
+}
+
+/// Entry of an op in the manifest of guest modules, one line of JSON.
+struct Manifest<'a> {
+    id: &'a OpId,
+    streaming: bool,
+    idempotent: bool,
+    cache_ttl: Option<u64>,
+    module: Option<&'a str>,
+    description: Option<String>,
+}
+
+impl Manifest<'_> {
+    /// The entry as a string expression, as the id may only be known once expanded.
+    fn tokens(&self) -> TokenStream2 {
+        let open = r#"{"id":"#;
+        let id = self.id.json();
+        let rest = format!(
+            r#","kind":"{}","module":{},"idempotent":{},"cache_ttl":{},"description":{}}}"#,
+            if self.streaming { "stream" } else { "op" },
+            self.module.map_or_else(|| "null".to_string(), json_string),
+            self.idempotent,
+            self.cache_ttl
+                .map_or_else(|| "null".to_string(), |ttl| ttl.to_string()),
+            self.description
+                .as_deref()
+                .map_or_else(|| "null".to_string(), json_string),
+        ) + "\n";
+
+        quote!(::std::concat!(#open, #id, #rest))
+    }
+}
+
+impl OpId {
+    /// The id as a string expression.
+    fn tokens(&self) -> TokenStream2 {
+        let id = &self.id;
+
+        match &self.namespace {
+            None => quote!(#id),
+            Some(Namespace::Name(name)) => {
+                let id = format!("{}::{}", name, id);
+                quote!(#id)
+            }
+            Some(Namespace::Crate) => {
+                quote!(::std::concat!(::std::env!("CARGO_CRATE_NAME"), "::", #id))
+            }
+            Some(Namespace::Module) => quote!(::std::concat!(::std::module_path!(), "::", #id)),
+        }
+    }
+
+    /// The id as a JSON string expression. Crate names and module paths need no escaping.
+    fn json(&self) -> TokenStream2 {
+        let quoted = json_string(&self.id);
+        let escaped = &quoted[1..quoted.len() - 1];
+
+        match &self.namespace {
+            None => quote!(#quoted),
+            Some(Namespace::Name(name)) => {
+                let id = json_string(&format!("{}::{}", name, self.id));
+                quote!(#id)
+            }
+            Some(Namespace::Crate) => quote! {
+                ::std::concat!("\"", ::std::env!("CARGO_CRATE_NAME"), "::", #escaped, "\"")
+            },
+            Some(Namespace::Module) => {
+                quote!(::std::concat!("\"", ::std::module_path!(), "::", #escaped, "\""))
+            }
+        }
+    }
+}
+
+/// Description of an op, from its doc comments.
+fn description(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(nv)) => match nv.lit {
+                Lit::Str(s) => Some(s.value().trim().to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+
+    let description = lines.join("\n").trim().to_string();
+
+    if description.is_empty() {
+        None
+    } else {
+        Some(description)
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// Id of an impl block setting `const ID` to a string literal.
+fn literal_id(imp: &ItemImpl) -> Option<String> {
+    imp.items.iter().find_map(|item| match item {
+        ImplItem::Const(c) if c.ident == "ID" => match &c.expr {
+            Expr::Lit(lit) => match &lit.lit {
+                Lit::Str(s) => Some(s.value()),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+/// Whether an impl block sets the const `name` to `true`.
+fn const_true(imp: &ItemImpl, name: &str) -> bool {
+    imp.items.iter().any(|item| match item {
+        ImplItem::Const(c) if c.ident == name => {
+            matches!(&c.expr, Expr::Lit(lit) if matches!(lit.lit, Lit::Bool(ref b) if b.value))
+        }
+        _ => false,
+    })
+}
+
+fn module(id: Option<String>) -> TokenStream2 {
+    match id {
+        Some(id) => quote!(::std::option::Option::Some(#id)),
+        None => quote!(::std::option::Option::None),
+    }
+}
+
+fn cache_ttl(seconds: Option<u64>) -> TokenStream2 {
+    match seconds {
+        Some(seconds) => {
+            quote!(::std::option::Option::Some(::std::time::Duration::from_secs(#seconds)))
+        }
+        None => quote!(::std::option::Option::None),
+    }
+}
+
+fn result_types(ty: &Type) -> Option<(&Type, &Type)> {
+    let segment = match ty {
+        Type::Path(p) if p.qself.is_none() => p.path.segments.last()?,
+        _ => return None,
+    };
+
+    if segment.ident != "Result" {
+        return None;
+    }
+
+    let args = match &segment.arguments {
+        PathArguments::AngleBracketed(a) if a.args.len() == 2 => &a.args,
+        _ => return None,
+    };
+
+    match (&args[0], &args[1]) {
+        (GenericArgument::Type(output), GenericArgument::Type(error)) => Some((output, error)),
+        _ => None,
+    }
+}
+
+fn type_name(ty: &Type) -> Result<String, Error> {
+    match ty {
+        Type::Path(p) if p.qself.is_none() => p
+            .path
+            .segments
+            .last()
+            .map(|s| s.ident.to_string())
+            .ok_or_else(|| Error::new(ty.span(), "expected a type name")),
+        _ => Err(Error::new(
+            ty.span(),
+            "cannot derive an op id for this type, specify one with `id = \"...\"`",
+        )),
+    }
+}
+
+fn pascal_case(s: &str) -> String {
+    s.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn snake_case(s: &str) -> String {
+    let chars: Vec<char> = s.chars().collect();
+    let mut out = String::new();
+
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            let prev = chars[i - 1];
+            let next_is_lower = chars.get(i + 1).map(|n| n.is_lowercase()).unwrap_or(false);
+
+            if prev.is_lowercase() || prev.is_numeric() || (prev.is_uppercase() && next_is_lower) {
+                out.push('_');
+            }
+        }
+
+        out.extend(c.to_lowercase());
+    }
+
+    out
+}
@@ -0,0 +1,399 @@
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::time;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::transport::{BoxFuture, Reply, Request, Transport};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use futures::StreamExt;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use std::sync::{Arc, Mutex};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use std::time::Duration;
+
+/// Spreads requests across several heimdall instances running the same modules, see
+/// `Dispatcher::for_endpoints`.
+///
+/// An endpoint is marked unhealthy when a request to it fails with a network error, and is left
+/// out until `probe_interval` has passed. The next request picked for it then serves as a probe,
+/// bringing it back if it gets a reply. When no endpoint is healthy, requests go to the one due
+/// to be probed first.
+///
+/// A request is never resent to another endpoint by the balancer itself, as the failed one may
+/// have executed it, but retries of idempotent ops go to the next endpoint picked.
+///
+/// Clones share the state of their endpoints.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+#[derive(Clone)]
+pub struct Balancer {
+    strategy: Strategy,
+    endpoints: Vec<Arc<Endpoint>>,
+    next: Arc<AtomicUsize>,
+    probe_interval: Duration,
+}
+
+/// How a `Balancer` picks the endpoint of each request among the healthy ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// Each endpoint in turn.
+    RoundRobin,
+    /// The endpoint with the fewest requests in flight, including replies being received.
+    LeastInFlight,
+    /// The first endpoint, in the order they were added, failing over to the next ones.
+    Priority,
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+struct Endpoint {
+    transport: Box<dyn Transport>,
+    /// Path heimdall's routes are under on this endpoint.
+    prefix: String,
+    in_flight: AtomicUsize,
+    health: Mutex<Health>,
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+enum Health {
+    Healthy,
+    /// Left out until the given time, in milliseconds since the unix epoch.
+    Unhealthy {
+        until: u64,
+    },
+    /// A probe is in flight.
+    Probing,
+}
+
+/// A request in flight to an endpoint, until its reply is received in full or dropped.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+struct Lease {
+    endpoint: Arc<Endpoint>,
+    probe: bool,
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Balancer {
+    pub fn new(strategy: Strategy) -> Self {
+        Balancer {
+            strategy,
+            endpoints: Vec::new(),
+            next: Arc::new(AtomicUsize::new(0)),
+            probe_interval: Duration::from_secs(10),
+        }
+    }
+
+    /// Adds the heimdall instance at `base_url`, such as `http://10.0.0.2:8080`, reached over
+    /// HTTP.
+    pub fn with_url(self, base_url: &str) -> Self {
+        let (origin, prefix) = crate::dispatcher::split_origin(base_url);
+
+        #[cfg(feature = "local-native")]
+        let transport = crate::transport::http::HttpTransport::new(origin);
+        #[cfg(not(feature = "local-native"))]
+        let transport = crate::transport::fetch::FetchTransport::new(origin);
+
+        self.add(Box::new(transport), prefix)
+    }
+
+    /// Adds a heimdall instance reached through `transport`.
+    pub fn with_transport<T>(self, transport: T) -> Self
+    where
+        T: Transport + 'static,
+    {
+        self.add(Box::new(transport), "")
+    }
+
+    /// Sets how long unhealthy endpoints are left out before being probed, 10 seconds by default.
+    pub fn with_probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    /// Whether each endpoint, in the order they were added, is healthy.
+    pub fn health(&self) -> Vec<bool> {
+        self.endpoints
+            .iter()
+            .map(|endpoint| matches!(*endpoint.health.lock().unwrap(), Health::Healthy))
+            .collect()
+    }
+
+    fn add(mut self, transport: Box<dyn Transport>, prefix: &str) -> Self {
+        self.endpoints.push(Arc::new(Endpoint {
+            transport,
+            prefix: prefix.to_string(),
+            in_flight: AtomicUsize::new(0),
+            health: Mutex::new(Health::Healthy),
+        }));
+
+        self
+    }
+
+    /// Picks the endpoint of a request.
+    fn lease(&self) -> Option<Lease> {
+        let now = time::now_millis();
+        let count = self.endpoints.len();
+
+        let start = match self.strategy {
+            Strategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % count.max(1),
+            Strategy::LeastInFlight | Strategy::Priority => 0,
+        };
+
+        let order = (0..count).map(|i| &self.endpoints[(start + i) % count]);
+
+        let mut available = order.filter(|endpoint| match *endpoint.health.lock().unwrap() {
+            Health::Healthy => true,
+            Health::Unhealthy { until } => until <= now,
+            Health::Probing => false,
+        });
+
+        let picked = match self.strategy {
+            Strategy::LeastInFlight => {
+                available.min_by_key(|endpoint| endpoint.in_flight.load(Ordering::Relaxed))
+            }
+            Strategy::RoundRobin | Strategy::Priority => available.next(),
+        };
+
+        let endpoint = match picked {
+            Some(endpoint) => endpoint,
+            None => self.endpoints.iter().min_by_key(|endpoint| {
+                match *endpoint.health.lock().unwrap() {
+                    Health::Healthy | Health::Probing => 0,
+                    Health::Unhealthy { until } => until,
+                }
+            })?,
+        };
+
+        let probe = {
+            let mut health = endpoint.health.lock().unwrap();
+
+            match *health {
+                Health::Unhealthy { until } if until <= now => {
+                    *health = Health::Probing;
+                    true
+                }
+                _ => false,
+            }
+        };
+
+        endpoint.in_flight.fetch_add(1, Ordering::Relaxed);
+
+        Some(Lease {
+            endpoint: endpoint.clone(),
+            probe,
+        })
+    }
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Transport for Balancer {
+    fn send(&self, mut request: Request) -> BoxFuture<'_, Result<Reply, String>> {
+        Box::pin(async move {
+            let mut lease = self.lease().ok_or("no endpoints")?;
+            request.path = format!("{}{}", lease.endpoint.prefix, request.path);
+
+            let result = lease.endpoint.transport.send(request).await;
+            lease.record(result.is_ok(), self.probe_interval);
+
+            let mut reply = result?;
+            reply.body = Box::pin(reply.body.map(move |chunk| {
+                let _lease = &lease;
+                chunk
+            }));
+
+            Ok(reply)
+        })
+    }
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Lease {
+    /// Marks the endpoint healthy if it replied, and unhealthy otherwise.
+    fn record(&mut self, replied: bool, probe_interval: Duration) {
+        self.probe = false;
+
+        *self.endpoint.health.lock().unwrap() = if replied {
+            Health::Healthy
+        } else {
+            Health::Unhealthy {
+                until: time::now_millis() + probe_interval.as_millis() as u64,
+            }
+        };
+    }
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.endpoint.in_flight.fetch_sub(1, Ordering::Relaxed);
+
+        // A probe dropped before its outcome is known lets the next request probe instead.
+        if self.probe {
+            let mut health = self.endpoint.health.lock().unwrap();
+
+            if let Health::Probing = *health {
+                *health = Health::Unhealthy { until: 0 };
+            }
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "local-native", feature = "local-browser")))]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::sync::atomic::AtomicBool;
+
+    const PROBE_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// A heimdall instance replying with its name while it is up.
+    #[derive(Clone)]
+    struct Stub {
+        name: &'static str,
+        up: Arc<AtomicBool>,
+        sent: Arc<AtomicUsize>,
+    }
+
+    impl Stub {
+        fn new(name: &'static str) -> Self {
+            Stub {
+                name,
+                up: Arc::new(AtomicBool::new(true)),
+                sent: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        fn set_up(&self, up: bool) {
+            self.up.store(up, Ordering::SeqCst);
+        }
+
+        fn sent(&self) -> usize {
+            self.sent.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Transport for Stub {
+        fn send(&self, _request: Request) -> BoxFuture<'_, Result<Reply, String>> {
+            Box::pin(async move {
+                self.sent.fetch_add(1, Ordering::SeqCst);
+
+                if !self.up.load(Ordering::SeqCst) {
+                    return Err(format!("{} is down", self.name));
+                }
+
+                let name = self.name;
+                let body = futures::stream::once(async move { Ok(name.as_bytes().to_vec()) });
+
+                Ok(Reply {
+                    status: 200,
+                    headers: Vec::new(),
+                    #[cfg(not(target_arch = "wasm32"))]
+                    body: body.boxed(),
+                    #[cfg(target_arch = "wasm32")]
+                    body: body.boxed_local(),
+                })
+            })
+        }
+    }
+
+    fn balancer(strategy: Strategy, stubs: &[&Stub]) -> Balancer {
+        stubs
+            .iter()
+            .fold(Balancer::new(strategy), |balancer, stub| {
+                balancer.with_transport((*stub).clone())
+            })
+            .with_probe_interval(PROBE_INTERVAL)
+    }
+
+    /// Sends a request, returning the name of the endpoint that replied.
+    fn send(balancer: &Balancer) -> Result<String, String> {
+        let request = Request {
+            path: "/m/execute".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+
+        block_on(async {
+            let reply = balancer.send(request).await?;
+            let body = reply.bytes().await?;
+            Ok(String::from_utf8(body).unwrap())
+        })
+    }
+
+    #[test]
+    fn spreads_requests_round_robin() {
+        let (a, b) = (Stub::new("a"), Stub::new("b"));
+        let balancer = balancer(Strategy::RoundRobin, &[&a, &b]);
+
+        let replies: Vec<_> = (0..4).map(|_| send(&balancer).unwrap()).collect();
+
+        assert_eq!(replies, vec!["a", "b", "a", "b"]);
+    }
+
+    #[test]
+    fn leaves_out_endpoints_failing_with_network_errors() {
+        let (a, b) = (Stub::new("a"), Stub::new("b"));
+        let balancer = balancer(Strategy::Priority, &[&a, &b]);
+        a.set_up(false);
+
+        assert_eq!(send(&balancer), Err("a is down".to_string()));
+        assert_eq!(balancer.health(), vec![false, true]);
+
+        assert_eq!(send(&balancer).unwrap(), "b");
+        assert_eq!(send(&balancer).unwrap(), "b");
+        assert_eq!(a.sent(), 1);
+    }
+
+    #[test]
+    fn brings_endpoints_back_once_a_probe_gets_a_reply() {
+        let (a, b) = (Stub::new("a"), Stub::new("b"));
+        let balancer = balancer(Strategy::Priority, &[&a, &b]);
+        a.set_up(false);
+        send(&balancer).unwrap_err();
+
+        a.set_up(true);
+        assert_eq!(send(&balancer).unwrap(), "b");
+
+        std::thread::sleep(PROBE_INTERVAL);
+        assert_eq!(send(&balancer).unwrap(), "a");
+        assert_eq!(balancer.health(), vec![true, true]);
+    }
+
+    #[test]
+    fn keeps_endpoints_out_when_their_probe_fails() {
+        let (a, b) = (Stub::new("a"), Stub::new("b"));
+        let balancer = balancer(Strategy::Priority, &[&a, &b]);
+        a.set_up(false);
+        send(&balancer).unwrap_err();
+
+        std::thread::sleep(PROBE_INTERVAL);
+        send(&balancer).unwrap_err();
+
+        assert_eq!(balancer.health(), vec![false, true]);
+        assert_eq!(send(&balancer).unwrap(), "b");
+        assert_eq!(a.sent(), 2);
+    }
+
+    #[test]
+    fn sends_to_the_endpoint_due_first_when_all_are_down() {
+        let (a, b) = (Stub::new("a"), Stub::new("b"));
+        let balancer = balancer(Strategy::RoundRobin, &[&a, &b]);
+        a.set_up(false);
+        b.set_up(false);
+
+        send(&balancer).unwrap_err();
+        std::thread::sleep(Duration::from_millis(5));
+        send(&balancer).unwrap_err();
+        assert_eq!(balancer.health(), vec![false, false]);
+
+        // Both are left out, so requests still go to the one marked unhealthy first.
+        b.set_up(true);
+        assert_eq!(send(&balancer), Err("a is down".to_string()));
+        assert_eq!((a.sent(), b.sent()), (2, 1));
+    }
+
+    #[test]
+    fn fails_without_endpoints() {
+        let balancer = Balancer::new(Strategy::RoundRobin);
+
+        assert_eq!(send(&balancer), Err("no endpoints".to_string()));
+    }
+}
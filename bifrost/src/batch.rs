@@ -0,0 +1,265 @@
+use crate::call::Interrupt;
+use crate::dispatcher::Response;
+use crate::format::Format;
+use crate::op::Op;
+#[cfg(feature = "debug")]
+use crate::protocol;
+#[cfg(feature = "debug")]
+use crate::protocol::Handler;
+use crate::protocol::{Call, Outcome};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+/// A set of ops sent together with `Dispatcher::send_batch`, created by `Dispatcher::batch`.
+///
+/// Ops are executed in the order they were added, each yielding its own result.
+pub struct Batch {
+    format: Format,
+    target: Target,
+    entries: Vec<Entry>,
+}
+
+/// Module a batch or pipeline is sent to, which is the one executing its first op. Ops executed
+/// by another module are rejected when added, as heimdall runs all ops of a request in a single
+/// guest.
+pub(crate) struct Target {
+    /// Module of the dispatcher, executing ops that do not declare their own, unless ops are
+    /// executed locally.
+    dispatcher: Option<String>,
+    module: Option<String>,
+}
+
+struct Entry {
+    op: &'static str,
+    payload: Result<Vec<u8>, String>,
+    #[cfg_attr(
+        not(any(feature = "local-native", feature = "local-browser")),
+        allow(dead_code)
+    )]
+    idempotent: bool,
+    #[cfg(feature = "debug")]
+    exec: Handler,
+}
+
+/// Identifies the result of an op within a batch.
+pub struct Handle<T> {
+    index: usize,
+    op: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+/// Results of a batch, retrieved per op through the handles returned by `Batch::add`.
+pub struct BatchResults {
+    format: Format,
+    results: Result<Vec<Result<Outcome, String>>, Failure>,
+}
+
+/// Failure of the batch as a whole, reported for each of its ops.
+#[cfg_attr(
+    not(any(feature = "local-native", feature = "local-browser")),
+    allow(dead_code)
+)]
+pub(crate) enum Failure {
+    Network(String),
+    Request(u16, String),
+    Parse(String),
+    Interrupted(Interrupt),
+    CircuitOpen,
+}
+
+impl Batch {
+    pub(crate) fn new(format: Format, module: Option<&str>) -> Self {
+        Batch {
+            format,
+            target: Target::new(module),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds an op to the batch. An op executed by another module than the first op of the batch
+    /// is not sent, and its result is a `ParseError`.
+    pub fn add<T>(&mut self, op: &T) -> Handle<T>
+    where
+        T: Op + Serialize + DeserializeOwned,
+        T::Output: Serialize,
+        T::Error: Serialize,
+    {
+        let payload = self
+            .target
+            .admit(<T as Op>::id(), T::MODULE)
+            .and_then(|()| self.format.serialize(op));
+
+        self.entries.push(Entry {
+            op: <T as Op>::id(),
+            payload,
+            idempotent: T::IDEMPOTENT,
+            #[cfg(feature = "debug")]
+            exec: protocol::exec::<T>,
+        });
+
+        Handle {
+            index: self.entries.len() - 1,
+            op: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the batch can be retried, which requires all of its ops to be idempotent.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    pub(crate) fn is_idempotent(&self) -> bool {
+        self.entries.iter().all(|entry| entry.idempotent)
+    }
+
+    /// Module the batch is sent to, see `Target`.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    pub(crate) fn module(&self) -> Option<&str> {
+        self.target.module()
+    }
+
+    /// Calls to send, leaving out ops that could not be serialized.
+    pub(crate) fn calls(&self) -> Vec<Call> {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                entry.payload.as_ref().ok().map(|payload| Call {
+                    op: entry.op.to_string(),
+                    payload: payload.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Executes each op in place, as a guest would.
+    #[cfg(feature = "debug")]
+    pub(crate) fn exec(&self) -> Vec<Outcome> {
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .payload
+                    .as_ref()
+                    .ok()
+                    .map(|payload| (entry.exec)(payload, self.format, self.format))
+            })
+            .collect()
+    }
+
+    /// Pairs outcomes, in the order of `calls`, back up with the ops of the batch.
+    pub(crate) fn results(&self, format: Format, outcomes: Vec<Outcome>) -> BatchResults {
+        let mut outcomes = outcomes.into_iter();
+
+        let results = self
+            .entries
+            .iter()
+            .map(|entry| match &entry.payload {
+                Err(e) => Err(e.clone()),
+                Ok(_) => outcomes
+                    .next()
+                    .ok_or_else(|| format!("missing result for op {}", entry.op)),
+            })
+            .collect();
+
+        BatchResults {
+            format,
+            results: Ok(results),
+        }
+    }
+
+    pub(crate) fn failed(&self, failure: Failure) -> BatchResults {
+        BatchResults {
+            format: self.format,
+            results: Err(failure),
+        }
+    }
+}
+
+impl Target {
+    /// Target of a batch or pipeline created by a dispatcher for `module`, or executing ops
+    /// locally if `None`.
+    pub(crate) fn new(module: Option<&str>) -> Self {
+        Target {
+            dispatcher: module.map(str::to_string),
+            module: None,
+        }
+    }
+
+    /// Checks that the op `op`, declaring `module`, is executed by the same module as the ops
+    /// added before it.
+    pub(crate) fn admit(&mut self, op: &str, module: Option<&str>) -> Result<(), String> {
+        let module = match (module, &self.dispatcher) {
+            (_, None) => return Ok(()),
+            (Some(module), _) => module,
+            (None, Some(dispatcher)) => dispatcher,
+        };
+
+        match &self.module {
+            None => {
+                self.module = Some(module.to_string());
+                Ok(())
+            }
+            Some(target) if target == module => Ok(()),
+            Some(target) => Err(format!(
+                "op {} is executed by module {}, not by {} as the ops before it",
+                op, module, target
+            )),
+        }
+    }
+
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    pub(crate) fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+}
+
+impl BatchResults {
+    /// Returns the result of an op, with errors affecting the whole batch repeated for each op.
+    ///
+    /// Errors specific to the op are reported as they would be for a single op, the guest failing
+    /// to find or decode it becoming a `RequestError`.
+    pub fn get<T>(&self, handle: Handle<T>) -> Response<T::Output, T::Error>
+    where
+        T: Op,
+        T::Output: DeserializeOwned,
+        T::Error: DeserializeOwned,
+    {
+        let results = match &self.results {
+            Ok(results) => results,
+            Err(Failure::Network(e)) => return Response::NetworkError(e.clone()),
+            Err(Failure::Request(status, e)) => return Response::RequestError(*status, e.clone()),
+            Err(Failure::Parse(e)) => return Response::ParseError(e.clone()),
+            Err(Failure::Interrupted(interrupt)) => return interrupt.response(),
+            Err(Failure::CircuitOpen) => return Response::CircuitOpen,
+        };
+
+        match results.get(handle.index) {
+            None => Response::ParseError(format!("no op at index {} in batch", handle.index)),
+            Some(Err(e)) => Response::ParseError(e.clone()),
+            Some(Ok(Outcome::Success(bytes))) => match self.format.deserialize(bytes) {
+                Ok(v) => Response::Success(v),
+                Err(e) => Response::ParseError(e),
+            },
+            Some(Ok(Outcome::OpError(bytes))) => match self.format.deserialize(bytes) {
+                Ok(e) => Response::OpError(e),
+                Err(e) => Response::ParseError(e),
+            },
+            Some(Ok(Outcome::UnknownOp(e))) => Response::RequestError(404, e.clone()),
+            Some(Ok(Outcome::InputDecodeError(e))) => Response::RequestError(400, e.clone()),
+            Some(Ok(Outcome::OutputEncodeError(e))) => Response::RequestError(500, e.clone()),
+        }
+    }
+}
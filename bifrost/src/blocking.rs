@@ -0,0 +1,851 @@
+use crate::balancer::{Balancer, Strategy};
+use crate::batch::{Batch, BatchResults, Failure};
+use crate::breaker::{CircuitBreaker, CircuitState};
+use crate::cache::{CacheStore, Slot};
+use crate::call::{CallOptions, Interrupt};
+use crate::dispatcher::{self, Response};
+use crate::exchange::{self, Attempt, Handling, Head};
+use crate::format::Format;
+use crate::interceptor::Interceptor;
+use crate::op::{Op, StreamingOp};
+use crate::pipeline::{Pipeline, PipelineError};
+use crate::protocol::{Outcome, StageFailure};
+use crate::retry::{Retries, RetryPolicy};
+use crate::stream;
+use crate::time;
+use crate::transport::{Body, Request, Transport};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{self, BufRead, BufReader, Read};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+/// Sends ops to heimdall from synchronous code, such as CLI tools and build scripts, blocking the
+/// calling thread until their responses are received.
+///
+/// Calls behave as they do with `Dispatcher`, and resolve to the same `Response`s. Cancellations
+/// are only checked before each request and each streamed item, as a request in flight cannot be
+/// interrupted, and identical calls are not coalesced.
+///
+/// Requests are sent with `reqwest`'s blocking client, or through a `Transport` driven by a runtime
+/// of the dispatcher's own. Neither may be used from within an async runtime.
+pub struct BlockingDispatcher {
+    link: Link,
+    /// Path heimdall's routes are under, empty unless heimdall is served under a prefix.
+    base: String,
+    /// Module ops are sent to, unless they declare their own.
+    module: String,
+    headers: Vec<(String, String)>,
+    format: Format,
+    retry: Option<RetryPolicy>,
+    timeout: Option<Duration>,
+    breaker: Option<CircuitBreaker>,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+    cache: Option<Arc<dyn CacheStore>>,
+}
+
+/// Items of a `StreamingOp`, read as heimdall sends them and ending after the first error.
+pub struct Items<T, E> {
+    state: State,
+    options: CallOptions,
+    items: PhantomData<(T, E)>,
+}
+
+enum State {
+    Failed(Option<Error>),
+    Reading(BufReader<Box<dyn Read + Send>>),
+    Done,
+}
+
+/// How requests reach heimdall.
+enum Link {
+    Http {
+        client: reqwest::blocking::Client,
+        origin: String,
+    },
+    Transport {
+        transport: Arc<dyn Transport>,
+        runtime: Arc<Runtime>,
+    },
+}
+
+/// Body of a reply received through a transport, blocking on the runtime for each of its chunks.
+struct Chunks {
+    body: Body,
+    runtime: Arc<Runtime>,
+    deadline: Option<u64>,
+    chunk: Vec<u8>,
+    read: usize,
+}
+
+/// Reason a call did not complete.
+enum Error {
+    Network(String),
+    CircuitOpen,
+    /// The body of a reply could not be read.
+    Body(String),
+    Interrupted(Interrupt),
+    /// Heimdall failed to execute the request.
+    Rejected(u16, String),
+}
+
+struct Reply {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Box<dyn Read + Send>,
+}
+
+impl BlockingDispatcher {
+    /// Creates a dispatcher for the heimdall execute route at `url`, such as
+    /// `http://localhost:8080/my-module/execute`.
+    pub fn create(url: String) -> Self {
+        Self::with_client(reqwest::blocking::Client::new(), url)
+    }
+
+    pub fn with_client(client: reqwest::blocking::Client, url: String) -> Self {
+        let (origin, base, module) = dispatcher::split_url(&url);
+        Self::over_http(client, origin, base, module)
+    }
+
+    /// Creates a dispatcher for the heimdall instance at `base_url`, sending ops to the module
+    /// `module_id` unless they declare their own, as `Dispatcher::for_module` does.
+    pub fn for_module(base_url: String, module_id: &str) -> Self {
+        let (origin, base) = dispatcher::split_origin(&base_url);
+        Self::over_http(reqwest::blocking::Client::new(), origin, base, module_id)
+    }
+
+    /// Creates a dispatcher spreading requests across the heimdall instances at `base_urls`
+    /// according to `strategy`, as `Dispatcher::for_endpoints` does.
+    pub fn for_endpoints(base_urls: Vec<String>, module_id: &str, strategy: Strategy) -> Self {
+        let balancer = base_urls
+            .iter()
+            .fold(Balancer::new(strategy), |balancer, url| {
+                balancer.with_url(url)
+            });
+
+        Self::with_transport(balancer, module_id)
+    }
+
+    /// Creates a dispatcher for the module `module_id`, reached through `transport`, as
+    /// `Dispatcher::with_transport` does.
+    pub fn with_transport<T>(transport: T, module_id: &str) -> Self
+    where
+        T: Transport + 'static,
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("failed to start the runtime of a blocking dispatcher");
+
+        let link = Link::Transport {
+            transport: Arc::new(transport),
+            runtime: Arc::new(runtime),
+        };
+
+        Self::build(link, "", module_id)
+    }
+
+    fn over_http(
+        client: reqwest::blocking::Client,
+        origin: &str,
+        base: &str,
+        module: &str,
+    ) -> Self {
+        let link = Link::Http {
+            client,
+            origin: origin.to_string(),
+        };
+
+        Self::build(link, base, module)
+    }
+
+    fn build(link: Link, base: &str, module: &str) -> Self {
+        BlockingDispatcher {
+            link,
+            base: base.to_string(),
+            module: module.to_string(),
+            headers: Vec::new(),
+            format: Format::default(),
+            retry: None,
+            timeout: None,
+            breaker: None,
+            interceptors: Vec::new(),
+            cache: None,
+        }
+    }
+
+    /// Adds a header to every request, exposed to ops through `Context::headers`.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the format ops and their results are serialized in, JSON by default.
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Retries failed requests for idempotent ops according to `policy`.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Sets the time after which calls resolve to `Response::Timeout`, unless overridden with
+    /// `CallOptions::with_timeout`.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Guards requests with `breaker`, which keeps a circuit per module and can be shared with
+    /// other dispatchers, including async ones.
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// State of the circuit of the module of the dispatcher, if it has a circuit breaker.
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.breaker
+            .as_ref()
+            .map(|breaker| breaker.state(&self.module))
+    }
+
+    /// Adds an interceptor, called before each request is sent and once its reply is received.
+    pub fn with_interceptor<I>(mut self, interceptor: I) -> Self
+    where
+        I: Interceptor + 'static,
+    {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Caches outputs of ops declaring a `CACHE_TTL` in `store`, as `Dispatcher::with_cache` does.
+    pub fn with_cache<S>(mut self, store: S) -> Self
+    where
+        S: CacheStore + 'static,
+    {
+        self.cache = Some(Arc::new(store));
+        self
+    }
+
+    /// Removes all cached outputs of the op `T`, as `Dispatcher::invalidate` does.
+    pub fn invalidate<T: Op>(&self) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate(T::MODULE.unwrap_or(&self.module), T::id());
+        }
+    }
+
+    /// Creates an empty batch of ops to send with `send_batch`.
+    pub fn batch(&self) -> Batch {
+        Batch::new(self.format, Some(&self.module))
+    }
+
+    /// Starts a pipeline of ops to send with `send_pipeline`.
+    pub fn pipeline<T>(&self, op: &T) -> Pipeline<T>
+    where
+        T: Op + Serialize + DeserializeOwned,
+        T::Output: Serialize,
+        T::Error: Serialize,
+    {
+        Pipeline::new(op, Some(&self.module))
+    }
+
+    pub fn send<T>(&self, op: &T) -> Response<T::Output, T::Error>
+    where
+        T: Op + Serialize,
+        T::Output: DeserializeOwned,
+        T::Error: DeserializeOwned,
+    {
+        self.send_with(op, &CallOptions::default())
+    }
+
+    /// Sends an op, with a timeout or cancellation specific to this call.
+    pub fn send_with<T>(&self, op: &T, options: &CallOptions) -> Response<T::Output, T::Error>
+    where
+        T: Op + Serialize,
+        T::Output: DeserializeOwned,
+        T::Error: DeserializeOwned,
+    {
+        let body = match self.format.serialize(op) {
+            Ok(body) => body,
+            Err(e) => return Response::ParseError(e),
+        };
+
+        let module = <T as Op>::MODULE.unwrap_or(&self.module);
+        let slot = Slot::new::<T>(self.cache.as_deref(), module, &self.headers, &body);
+
+        if let Some(output) = slot.as_ref().and_then(Slot::get) {
+            return Response::Success(output);
+        }
+
+        let reply = match self.post(
+            <T as Op>::MODULE,
+            "execute",
+            Some(<T as Op>::id()),
+            body,
+            T::IDEMPOTENT,
+            options,
+        ) {
+            Ok(reply) => reply,
+            Err(e) => return e.response(),
+        };
+
+        let format = reply.head().format(self.format);
+        let handling = reply.head().handling();
+
+        let bytes = match reply.bytes() {
+            Ok(bytes) => bytes,
+            Err(e) => return e.response(),
+        };
+
+        if handling == Handling::OpError {
+            return match format.deserialize(&bytes) {
+                Ok(e) => Response::OpError(e),
+                Err(e) => Response::ParseError(e),
+            };
+        }
+
+        match format.deserialize(&bytes) {
+            Ok(v) => {
+                if let Some(slot) = &slot {
+                    slot.put(format, bytes);
+                }
+
+                Response::Success(v)
+            }
+            Err(e) => Response::ParseError(e),
+        }
+    }
+
+    /// Sends all ops of a batch in a single request, executed in order by one guest instance.
+    pub fn send_batch(&self, batch: &Batch) -> BatchResults {
+        self.send_batch_with(batch, &CallOptions::default())
+    }
+
+    /// Sends a batch, with a timeout or cancellation specific to this call.
+    pub fn send_batch_with(&self, batch: &Batch, options: &CallOptions) -> BatchResults {
+        let body = match self.format.serialize(&batch.calls()) {
+            Ok(body) => body,
+            Err(e) => return batch.failed(Failure::Parse(e)),
+        };
+
+        let outcomes = self
+            .post(
+                batch.module(),
+                "batch",
+                None,
+                body,
+                batch.is_idempotent(),
+                options,
+            )
+            .and_then(|reply| {
+                let format = reply.head().format(self.format);
+                reply.bytes().map(|bytes| (format, bytes))
+            });
+
+        match outcomes {
+            Ok((format, bytes)) => match format.deserialize::<Vec<Outcome>>(&bytes) {
+                Ok(outcomes) => batch.results(format, outcomes),
+                Err(e) => batch.failed(Failure::Parse(e)),
+            },
+            Err(e) => batch.failed(e.failure()),
+        }
+    }
+
+    /// Sends a pipeline in a single request, returning the output of its last step or the first
+    /// failed step.
+    pub fn send_pipeline<T>(&self, pipeline: &Pipeline<T>) -> Response<T::Output, PipelineError>
+    where
+        T: Op,
+        T::Output: DeserializeOwned,
+    {
+        self.send_pipeline_with(pipeline, &CallOptions::default())
+    }
+
+    /// Sends a pipeline, with a timeout or cancellation specific to this call.
+    pub fn send_pipeline_with<T>(
+        &self,
+        pipeline: &Pipeline<T>,
+        options: &CallOptions,
+    ) -> Response<T::Output, PipelineError>
+    where
+        T: Op,
+        T::Output: DeserializeOwned,
+    {
+        let body = match pipeline.stages().and_then(|s| self.format.serialize(&s)) {
+            Ok(body) => body,
+            Err(e) => return Response::ParseError(e),
+        };
+
+        let reply = match self.post(
+            pipeline.module(),
+            "pipeline",
+            None,
+            body,
+            pipeline.is_idempotent(),
+            options,
+        ) {
+            Ok(reply) => reply,
+            Err(e) => return e.response(),
+        };
+
+        let format = reply.head().format(self.format);
+        let handling = reply.head().handling();
+
+        let bytes = match reply.bytes() {
+            Ok(bytes) => bytes,
+            Err(e) => return e.response(),
+        };
+
+        if handling == Handling::OpError {
+            match format.deserialize::<StageFailure>(&bytes) {
+                Ok(failure) => pipeline.failed(format, failure),
+                Err(e) => Response::ParseError(e),
+            }
+        } else {
+            match format.deserialize(&bytes) {
+                Ok(v) => Response::Success(v),
+                Err(e) => Response::ParseError(e),
+            }
+        }
+    }
+
+    /// Sends a streaming op, whose items are read as heimdall sends them.
+    pub fn send_stream<T>(&self, op: &T) -> Items<T::Item, T::Error>
+    where
+        T: StreamingOp + Serialize,
+        T::Item: DeserializeOwned,
+        T::Error: DeserializeOwned,
+    {
+        self.send_stream_with(op, &CallOptions::default())
+    }
+
+    /// Sends a streaming op, with a timeout or cancellation specific to this call, which covers
+    /// reading all of its items.
+    pub fn send_stream_with<T>(&self, op: &T, options: &CallOptions) -> Items<T::Item, T::Error>
+    where
+        T: StreamingOp + Serialize,
+        T::Item: DeserializeOwned,
+        T::Error: DeserializeOwned,
+    {
+        let state = match self.format.serialize(op) {
+            Err(e) => State::Failed(Some(Error::Body(e))),
+            Ok(body) => match self.post(
+                <T as StreamingOp>::MODULE,
+                "stream",
+                Some(<T as StreamingOp>::id()),
+                body,
+                T::IDEMPOTENT,
+                options,
+            ) {
+                Ok(reply) => State::Reading(BufReader::new(reply.body)),
+                Err(e) => State::Failed(Some(e)),
+            },
+        };
+
+        Items {
+            state,
+            options: options.clone(),
+            items: PhantomData,
+        }
+    }
+
+    /// Posts a request to a route of `module`, or of the module of the dispatcher, retrying it
+    /// according to the retry policy if `idempotent`, and reading the body of replies to failed
+    /// executions.
+    fn post(
+        &self,
+        module: Option<&str>,
+        route: &str,
+        op: Option<&str>,
+        body: Vec<u8>,
+        idempotent: bool,
+        options: &CallOptions,
+    ) -> Result<Reply, Error> {
+        let deadline = options
+            .timeout(self.timeout)
+            .map(|timeout| time::now_millis() + timeout.as_millis() as u64);
+
+        let module = module.unwrap_or(&self.module);
+        let headers = exchange::headers(self.format, route, op, deadline, &self.headers);
+
+        let mut retries = Retries::new(self.retry.as_ref(), idempotent, deadline);
+
+        loop {
+            if options.is_cancelled() {
+                return Err(Error::Interrupted(Interrupt::Cancelled));
+            }
+
+            let remaining = match deadline {
+                None => None,
+                Some(deadline) => match deadline.checked_sub(time::now_millis()) {
+                    Some(remaining) if remaining > 0 => Some(Duration::from_millis(remaining)),
+                    _ => return Err(Error::Interrupted(Interrupt::Timeout)),
+                },
+            };
+
+            let result = self.attempt(module, route, op, headers.clone(), &body, remaining);
+
+            let backoff = match &result {
+                Ok(reply) => retries.after(Some(reply.head())),
+                Err(Error::Network(_)) => retries.after(None),
+                Err(_) => None,
+            };
+
+            match backoff {
+                Some(backoff) => std::thread::sleep(remaining.map_or(backoff, |r| backoff.min(r))),
+                None => return result.and_then(Reply::checked),
+            }
+        }
+    }
+
+    /// Sends a request to `module` once, through the circuit breaker if there is one and the
+    /// interceptors.
+    fn attempt(
+        &self,
+        module: &str,
+        route: &str,
+        op: Option<&str>,
+        mut headers: Vec<(String, String)>,
+        body: &[u8],
+        timeout: Option<Duration>,
+    ) -> Result<Reply, Error> {
+        let attempt = Attempt::start(
+            self.breaker.as_ref(),
+            &self.interceptors,
+            module,
+            route,
+            op,
+            body,
+            &mut headers,
+        )
+        .ok_or(Error::CircuitOpen)?;
+
+        let path = exchange::path(&self.base, module, route);
+
+        match self.link.send(path, headers, body.to_vec(), timeout) {
+            Ok(reply) => {
+                attempt.end(Ok(reply.head()));
+                Ok(reply)
+            }
+            Err(Error::Network(e)) => {
+                attempt.end(Err(&e));
+                Err(Error::Network(e))
+            }
+            // As with a timed out `Dispatcher` call, the breaker and interceptors are left out.
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl Link {
+    /// Sends a request, failing with a network error or, if it is not replied to within
+    /// `timeout`, a timeout.
+    fn send(
+        &self,
+        path: String,
+        headers: Vec<(String, String)>,
+        body: Vec<u8>,
+        timeout: Option<Duration>,
+    ) -> Result<Reply, Error> {
+        let (transport, runtime) = match self {
+            Link::Http { client, origin } => {
+                let mut builder = client.post(format!("{}{}", origin, path));
+
+                for (name, value) in headers.iter() {
+                    builder = builder.header(name, value);
+                }
+
+                if let Some(timeout) = timeout {
+                    builder = builder.timeout(timeout);
+                }
+
+                return match builder.body(body).send() {
+                    Ok(response) => Ok(Reply::new(response)),
+                    Err(e) if e.is_timeout() => Err(Error::Interrupted(Interrupt::Timeout)),
+                    Err(e) => Err(Error::Network(e.to_string())),
+                };
+            }
+            Link::Transport { transport, runtime } => (transport, runtime),
+        };
+
+        let request = Request {
+            path,
+            headers,
+            body,
+        };
+
+        let reply = match timeout {
+            None => runtime.block_on(transport.send(request)),
+            Some(timeout) => runtime
+                .block_on(tokio::time::timeout(timeout, transport.send(request)))
+                .map_err(|_| Error::Interrupted(Interrupt::Timeout))?,
+        };
+
+        let reply = reply.map_err(Error::Network)?;
+
+        let body = Chunks {
+            body: reply.body,
+            runtime: runtime.clone(),
+            deadline: timeout.map(|timeout| time::now_millis() + timeout.as_millis() as u64),
+            chunk: Vec::new(),
+            read: 0,
+        };
+
+        Ok(Reply {
+            status: reply.status,
+            headers: reply.headers,
+            body: Box::new(body),
+        })
+    }
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.read == self.chunk.len() {
+            let next = match self.deadline {
+                None => self.runtime.block_on(self.body.next()),
+                Some(deadline) => {
+                    let remaining = deadline.saturating_sub(time::now_millis());
+                    let next =
+                        tokio::time::timeout(Duration::from_millis(remaining), self.body.next());
+
+                    self.runtime
+                        .block_on(next)
+                        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+                }
+            };
+
+            match next {
+                None => return Ok(0),
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.read = 0;
+                }
+                Some(Err(e)) => return Err(io::Error::other(e)),
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.read);
+        buf[..read].copy_from_slice(&self.chunk[self.read..self.read + read]);
+        self.read += read;
+        Ok(read)
+    }
+}
+
+impl<T, E> Iterator for Items<T, E>
+where
+    T: DeserializeOwned,
+    E: DeserializeOwned,
+{
+    type Item = Response<T, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = match &mut self.state {
+            State::Done => return None,
+            State::Failed(e) => {
+                let response = e.take().map(Error::response);
+                self.state = State::Done;
+                return response;
+            }
+            State::Reading(reader) => reader,
+        };
+
+        if self.options.is_cancelled() {
+            self.state = State::Done;
+            return Some(Response::Cancelled);
+        }
+
+        let mut line = Vec::new();
+
+        let response = loop {
+            line.clear();
+
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => break None,
+                Ok(_) if line.iter().all(u8::is_ascii_whitespace) => continue,
+                Ok(_) => break Some(stream::parse(&line)),
+                Err(e) => break Some(Error::from(e).response()),
+            }
+        };
+
+        if !matches!(response, Some(Response::Success(_))) {
+            self.state = State::Done;
+        }
+
+        response
+    }
+}
+
+impl Error {
+    fn response<T, E>(self) -> Response<T, E> {
+        match self {
+            Error::Network(e) => Response::NetworkError(e),
+            Error::CircuitOpen => Response::CircuitOpen,
+            Error::Body(e) => Response::ParseError(e),
+            Error::Interrupted(interrupt) => interrupt.response(),
+            Error::Rejected(status, message) => Response::RequestError(status, message),
+        }
+    }
+
+    fn failure(self) -> Failure {
+        match self {
+            Error::Network(e) => Failure::Network(e),
+            Error::CircuitOpen => Failure::CircuitOpen,
+            Error::Body(e) => Failure::Parse(e),
+            Error::Interrupted(interrupt) => Failure::Interrupted(interrupt),
+            Error::Rejected(status, message) => Failure::Request(status, message),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        if e.kind() == io::ErrorKind::TimedOut {
+            Error::Interrupted(Interrupt::Timeout)
+        } else {
+            Error::Body(e.to_string())
+        }
+    }
+}
+
+impl Reply {
+    fn new(body: reqwest::blocking::Response) -> Self {
+        let headers = body
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|v| (name.to_string(), v.to_string()))
+            })
+            .collect();
+
+        Reply {
+            status: body.status().as_u16(),
+            headers,
+            body: Box::new(body),
+        }
+    }
+
+    fn head(&self) -> Head<'_> {
+        Head::new(self.status, &self.headers)
+    }
+
+    fn bytes(mut self) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::new();
+        self.body.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Passes successful executions and op errors through, turning other replies into errors.
+    fn checked(self) -> Result<Reply, Error> {
+        match self.head().handling() {
+            Handling::Executed | Handling::OpError => Ok(self),
+            Handling::DeadlineExceeded => Err(Error::Interrupted(Interrupt::Timeout)),
+            Handling::Rejected(status) => {
+                let message = self
+                    .bytes()
+                    .map(|b| String::from_utf8_lossy(&b).into_owned())
+                    .unwrap_or_default();
+
+                Err(Error::Rejected(status, message))
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::mock::MockTransport;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Increment {
+        i: i32,
+    }
+
+    impl Op for Increment {
+        type Output = i32;
+        type Error = String;
+
+        const IDEMPOTENT: bool = true;
+
+        const ID: &'static str = "increment";
+    }
+
+    #[derive(Serialize)]
+    struct Count;
+
+    impl StreamingOp for Count {
+        type Item = u32;
+        type Error = String;
+
+        const ID: &'static str = "count";
+    }
+
+    fn dispatcher(mock: &MockTransport) -> BlockingDispatcher {
+        BlockingDispatcher::with_transport(mock.clone(), "counter")
+    }
+
+    #[test]
+    fn sends_ops_through_transports() {
+        let mock = MockTransport::new().with_handler(|op: Increment| Response::Success(op.i + 1));
+
+        let response = dispatcher(&mock).send(&Increment { i: 41 });
+
+        assert!(matches!(response, Response::Success(42)));
+        assert_eq!(mock.calls()[0].module, "counter");
+    }
+
+    #[test]
+    fn reads_op_errors() {
+        let mock = MockTransport::new().with_response::<Increment>(Response::OpError("no".into()));
+
+        let response = dispatcher(&mock).send(&Increment { i: 0 });
+
+        assert!(matches!(response, Response::OpError(e) if e == "no"));
+    }
+
+    #[test]
+    fn retries_failures_as_dispatchers_do() {
+        let mock = MockTransport::new().with_request_error::<Increment>(503, "unavailable");
+        let policy = RetryPolicy::new(3).with_backoff(Duration::ZERO, Duration::ZERO);
+
+        let response = dispatcher(&mock)
+            .with_retry(policy)
+            .send(&Increment { i: 0 });
+
+        assert!(matches!(response, Response::RequestError(503, e) if e == "unavailable"));
+        assert_eq!(mock.calls().len(), 3);
+    }
+
+    #[test]
+    fn answers_exceeded_deadlines_as_timeouts() {
+        let mock = MockTransport::new().with_response::<Increment>(Response::Timeout);
+
+        let response = dispatcher(&mock).send(&Increment { i: 0 });
+
+        assert!(matches!(response, Response::Timeout));
+    }
+
+    #[test]
+    fn reads_streamed_items_through_transports() {
+        let mock = MockTransport::new().with_items::<Count>(vec![1, 2], Some("done".to_string()));
+
+        let items: Vec<_> = dispatcher(&mock).send_stream(&Count).collect();
+
+        assert!(matches!(
+            items.as_slice(),
+            [Response::Success(1), Response::Success(2), Response::OpError(e)] if e == "done"
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::exchange::{Handling, Head};
+use crate::time;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Stops a dispatcher from sending requests to a module that keeps failing, see
+/// `Dispatcher::with_circuit_breaker`.
+///
+/// The breaker starts closed, letting requests through. After `failure_threshold` consecutive
+/// failures (network errors and 5xx responses other than op errors and exceeded deadlines) it
+/// opens, and calls fail fast with `Response::CircuitOpen` for `reset_timeout`. It then turns
+/// half-open, letting a single trial request through, which closes it again if it succeeds and
+/// reopens it otherwise.
+///
+/// Each module has its own circuit, so that a failing module does not block requests to the
+/// others a dispatcher routes to. Clones share their state, so that a breaker can be shared by
+/// several dispatchers.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    not(any(feature = "local-native", feature = "local-browser")),
+    allow(dead_code)
+)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    reset_timeout: Duration,
+    /// Circuits of the modules requests were sent to, closed until then.
+    circuits: Arc<Mutex<HashMap<String, State>>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are sent.
+    Closed,
+    /// Requests fail fast.
+    Open,
+    /// A trial request is let through to find out whether the module has recovered.
+    HalfOpen,
+}
+
+#[derive(Debug)]
+#[cfg_attr(
+    not(any(feature = "local-native", feature = "local-browser")),
+    allow(dead_code)
+)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    /// Open until the given time, in milliseconds since the unix epoch.
+    Open {
+        until: u64,
+    },
+    HalfOpen {
+        trial: bool,
+    },
+}
+
+/// Permission to send a request, whose outcome is reported with `record`.
+///
+/// A trial dropped without an outcome, such as when its call is cancelled, lets another trial
+/// through.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    module: String,
+    trial: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            reset_timeout,
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Current state of the circuit of the module `module_id`, such as to tell users that it is
+    /// unavailable.
+    pub fn state(&self, module_id: &str) -> CircuitState {
+        match self.circuits.lock().unwrap().get(module_id) {
+            None | Some(State::Closed { .. }) => CircuitState::Closed,
+            Some(State::Open { until }) if time::now_millis() < *until => CircuitState::Open,
+            Some(State::Open { .. } | State::HalfOpen { .. }) => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Closes the circuits of all modules, forgetting past failures.
+    pub fn reset(&self) {
+        self.circuits.lock().unwrap().clear();
+    }
+
+    /// Lets a request to `module` through, unless its circuit is open or already has a trial in
+    /// flight.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    pub(crate) fn acquire(&self, module: &str) -> Option<Permit<'_>> {
+        let mut circuits = self.circuits.lock().unwrap();
+        let state = circuits
+            .entry(module.to_string())
+            .or_insert(State::Closed { failures: 0 });
+
+        let trial = match *state {
+            State::Closed { .. } => false,
+            State::Open { until } if time::now_millis() < until => return None,
+            State::Open { .. } | State::HalfOpen { trial: false } => true,
+            State::HalfOpen { trial: true } => return None,
+        };
+
+        if trial {
+            *state = State::HalfOpen { trial: true };
+        }
+
+        Some(Permit {
+            breaker: self,
+            module: module.to_string(),
+            trial,
+        })
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30))
+    }
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Permit<'_> {
+    /// Records the outcome of a request from its reply, or a network error if `None`.
+    ///
+    /// Exceeded deadlines may be due to a short timeout of the caller rather than to the module,
+    /// so they do not count either way.
+    pub(crate) fn record_reply(self, reply: Option<Head>) {
+        match reply.map(|head| head.handling()) {
+            None => self.record(false),
+            Some(Handling::DeadlineExceeded) => {}
+            Some(Handling::Rejected(status)) => self.record(status < 500),
+            Some(Handling::Executed | Handling::OpError) => self.record(true),
+        }
+    }
+
+    pub(crate) fn record(mut self, success: bool) {
+        let trial = std::mem::replace(&mut self.trial, false);
+        let breaker = self.breaker;
+        let mut circuits = breaker.circuits.lock().unwrap();
+        let state = match circuits.get_mut(&self.module) {
+            Some(state) => state,
+            // The breaker was reset in the meantime.
+            None => return,
+        };
+        let opened = State::Open {
+            until: time::now_millis() + breaker.reset_timeout.as_millis() as u64,
+        };
+
+        *state = match (&*state, success) {
+            (State::Closed { .. }, true) => State::Closed { failures: 0 },
+            (State::Closed { failures }, false) if failures + 1 >= breaker.failure_threshold => {
+                opened
+            }
+            (State::Closed { failures }, false) => State::Closed {
+                failures: failures + 1,
+            },
+            (State::HalfOpen { .. }, true) if trial => State::Closed { failures: 0 },
+            (State::HalfOpen { .. }, false) if trial => opened,
+            // Requests let through before the breaker opened do not affect it anymore.
+            _ => return,
+        };
+    }
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if self.trial {
+            let mut circuits = self.breaker.circuits.lock().unwrap();
+
+            if let Some(state @ State::HalfOpen { trial: true }) = circuits.get_mut(&self.module) {
+                *state = State::HalfOpen { trial: false };
+            }
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "local-native", feature = "local-browser")))]
+mod tests {
+    use super::*;
+
+    const RESET_TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn fail(breaker: &CircuitBreaker, module: &str) {
+        breaker.acquire(module).unwrap().record(false);
+    }
+
+    fn opened() -> CircuitBreaker {
+        let breaker = CircuitBreaker::new(2, RESET_TIMEOUT);
+        fail(&breaker, "a");
+        fail(&breaker, "a");
+        breaker
+    }
+
+    #[test]
+    fn opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(2, RESET_TIMEOUT);
+
+        fail(&breaker, "a");
+        breaker.acquire("a").unwrap().record(true);
+        fail(&breaker, "a");
+        assert_eq!(breaker.state("a"), CircuitState::Closed);
+
+        fail(&breaker, "a");
+        assert_eq!(breaker.state("a"), CircuitState::Open);
+        assert!(breaker.acquire("a").is_none());
+    }
+
+    #[test]
+    fn keeps_a_circuit_per_module() {
+        let breaker = opened();
+
+        assert_eq!(breaker.state("b"), CircuitState::Closed);
+        assert!(breaker.acquire("b").is_some());
+    }
+
+    #[test]
+    fn lets_a_single_trial_through_once_half_open() {
+        let breaker = opened();
+        std::thread::sleep(RESET_TIMEOUT);
+        assert_eq!(breaker.state("a"), CircuitState::HalfOpen);
+
+        let trial = breaker.acquire("a").unwrap();
+        assert!(breaker.acquire("a").is_none());
+
+        trial.record(true);
+        assert_eq!(breaker.state("a"), CircuitState::Closed);
+    }
+
+    #[test]
+    fn reopens_when_the_trial_fails() {
+        let breaker = opened();
+        std::thread::sleep(RESET_TIMEOUT);
+
+        fail(&breaker, "a");
+        assert_eq!(breaker.state("a"), CircuitState::Open);
+    }
+
+    #[test]
+    fn lets_another_trial_through_when_one_is_dropped() {
+        let breaker = opened();
+        std::thread::sleep(RESET_TIMEOUT);
+
+        drop(breaker.acquire("a").unwrap());
+        assert!(breaker.acquire("a").is_some());
+    }
+
+    #[test]
+    fn ignores_requests_let_through_before_opening() {
+        let breaker = CircuitBreaker::new(1, RESET_TIMEOUT);
+        let late = breaker.acquire("a").unwrap();
+
+        fail(&breaker, "a");
+        late.record(true);
+        assert_eq!(breaker.state("a"), CircuitState::Open);
+    }
+}
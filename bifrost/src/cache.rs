@@ -0,0 +1,303 @@
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::format::Format;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::op::Op;
+use crate::time;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use std::time::Duration;
+
+/// Storage of cached op outputs, see `Dispatcher::with_cache`.
+///
+/// Outputs are keyed on the id of the module executing the op, the op id and its input. The input
+/// is given as a digest of the headers of the dispatcher followed by the hexadecimal encoding of
+/// the serialized op, so that it can be used in keys of string based storages and so that
+/// dispatchers sending different headers, such as the credentials of different callers, do not
+/// share outputs. Expired outputs are never used, but may be kept until they are overwritten or
+/// invalidated.
+///
+/// Headers added by interceptors are not part of the key, so callers told apart by interceptors
+/// alone should each have their own store.
+pub trait CacheStore: Send + Sync {
+    fn get(&self, module: &str, op: &str, input: &str) -> Option<CachedOutput>;
+
+    fn put(&self, module: &str, op: &str, input: &str, output: CachedOutput);
+
+    /// Removes all outputs of the op with the given id executed by `module`.
+    fn invalidate(&self, module: &str, op: &str);
+}
+
+/// A serialized op output.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CachedOutput {
+    /// Time after which the output is stale, in milliseconds since the unix epoch.
+    pub expires: u64,
+    /// Content type of the format the output is serialized in.
+    pub content_type: String,
+    #[serde(with = "serde_bytes")]
+    pub bytes: Vec<u8>,
+}
+
+/// Keeps cached outputs in memory, for as long as the store lives. Expired outputs are dropped
+/// when read and whenever an output is stored, so that the store only holds live outputs.
+#[derive(Default)]
+pub struct MemoryStore {
+    /// Outputs by module and op id, then by input.
+    ops: Mutex<HashMap<(String, String), HashMap<String, CachedOutput>>>,
+}
+
+/// Keeps cached outputs in the browser's `localStorage`, under keys starting with a prefix.
+#[cfg(feature = "local-browser")]
+pub struct LocalStorageStore {
+    prefix: String,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn get(&self, module: &str, op: &str, input: &str) -> Option<CachedOutput> {
+        let mut ops = self.ops.lock().unwrap();
+        let key = (module.to_string(), op.to_string());
+        let outputs = ops.get_mut(&key)?;
+
+        match outputs.get(input) {
+            Some(output) if output.expires > time::now_millis() => Some(output.clone()),
+            Some(_) => {
+                outputs.remove(input);
+
+                if outputs.is_empty() {
+                    ops.remove(&key);
+                }
+
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn put(&self, module: &str, op: &str, input: &str, output: CachedOutput) {
+        let mut ops = self.ops.lock().unwrap();
+        let now = time::now_millis();
+
+        ops.retain(|_, outputs| {
+            outputs.retain(|_, output| output.expires > now);
+            !outputs.is_empty()
+        });
+
+        ops.entry((module.to_string(), op.to_string()))
+            .or_default()
+            .insert(input.to_string(), output);
+    }
+
+    fn invalidate(&self, module: &str, op: &str) {
+        let key = (module.to_string(), op.to_string());
+        self.ops.lock().unwrap().remove(&key);
+    }
+}
+
+#[cfg(feature = "local-browser")]
+impl LocalStorageStore {
+    /// Creates a store whose keys start with `prefix`, such as `bifrost:`.
+    pub fn new(prefix: &str) -> Self {
+        LocalStorageStore {
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn key(&self, module: &str, op: &str, input: &str) -> String {
+        format!("{}{}/{}#{}", self.prefix, module, op, input)
+    }
+}
+
+#[cfg(feature = "local-browser")]
+impl CacheStore for LocalStorageStore {
+    fn get(&self, module: &str, op: &str, input: &str) -> Option<CachedOutput> {
+        use gloo::storage::{LocalStorage, Storage};
+
+        LocalStorage::get(self.key(module, op, input)).ok()
+    }
+
+    fn put(&self, module: &str, op: &str, input: &str, output: CachedOutput) {
+        use gloo::storage::{LocalStorage, Storage};
+
+        // A full storage only means that the output is not cached.
+        let _ = LocalStorage::set(self.key(module, op, input), output);
+    }
+
+    fn invalidate(&self, module: &str, op: &str) {
+        use gloo::storage::{LocalStorage, Storage};
+
+        let prefix = self.key(module, op, "");
+        let storage = LocalStorage::raw();
+
+        let keys: Vec<String> = (0..LocalStorage::length())
+            .filter_map(|i| storage.key(i).ok().flatten())
+            .filter(|key| key.starts_with(&prefix))
+            .collect();
+
+        for key in keys {
+            LocalStorage::delete(key);
+        }
+    }
+}
+
+/// Where the output of a call is cached, for dispatchers with a cache sending ops declaring a
+/// `CACHE_TTL`.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) struct Slot<'a> {
+    store: &'a dyn CacheStore,
+    module: &'a str,
+    op: &'static str,
+    input: String,
+    ttl: Duration,
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl<'a> Slot<'a> {
+    /// Slot of the op `T` serialized as `body` and executed by `module`, if it is cached, for a
+    /// dispatcher sending `headers`.
+    pub(crate) fn new<T: Op>(
+        store: Option<&'a dyn CacheStore>,
+        module: &'a str,
+        headers: &[(String, String)],
+        body: &[u8],
+    ) -> Option<Self> {
+        Some(Slot {
+            store: store?,
+            module,
+            op: T::id(),
+            input: input(headers, body),
+            ttl: T::CACHE_TTL?,
+        })
+    }
+
+    /// Output cached by an earlier call, unless it has expired.
+    pub(crate) fn get<T: DeserializeOwned>(&self) -> Option<T> {
+        let output = self.store.get(self.module, self.op, &self.input)?;
+
+        if output.expires <= time::now_millis() {
+            return None;
+        }
+
+        Format::from_content_type(&output.content_type)?
+            .deserialize(&output.bytes)
+            .ok()
+    }
+
+    /// Caches an output serialized in `format`.
+    pub(crate) fn put(&self, format: Format, bytes: Vec<u8>) {
+        let output = CachedOutput {
+            expires: time::now_millis() + self.ttl.as_millis() as u64,
+            content_type: format.content_type().to_string(),
+            bytes,
+        };
+
+        self.store.put(self.module, self.op, &self.input, output);
+    }
+}
+
+/// Encodes a serialized op sent with `headers` as a cache input.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+fn input(headers: &[(String, String)], bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{:016x}:{}", digest(headers), hex)
+}
+
+/// FNV-1a hash of `headers`, which stays the same across builds and sessions unlike the hashers
+/// of `std`, as cached outputs may outlive them in `localStorage`. The headers themselves are
+/// left out of keys as they may hold credentials.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+fn digest(headers: &[(String, String)]) -> u64 {
+    let bytes = headers.iter().flat_map(|(name, value)| {
+        name.bytes()
+            .chain(std::iter::once(0))
+            .chain(value.bytes())
+            .chain(std::iter::once(0))
+    });
+
+    bytes.fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(all(test, any(feature = "local-native", feature = "local-browser")))]
+mod tests {
+    use super::*;
+
+    struct Greet;
+
+    impl Op for Greet {
+        type Output = String;
+        type Error = String;
+
+        const CACHE_TTL: Option<Duration> = Some(Duration::from_secs(60));
+
+        const ID: &'static str = "greet";
+    }
+
+    fn headers(credentials: &str) -> Vec<(String, String)> {
+        vec![("authorization".to_string(), credentials.to_string())]
+    }
+
+    fn greeting<'a>(store: &'a MemoryStore, headers: &[(String, String)]) -> Slot<'a> {
+        Slot::new::<Greet>(Some(store), "greeter", headers, b"\"Bifrost\"").unwrap()
+    }
+
+    fn output(expires: u64) -> CachedOutput {
+        CachedOutput {
+            expires,
+            content_type: Format::Json.content_type().to_string(),
+            bytes: b"\"hi\"".to_vec(),
+        }
+    }
+
+    #[test]
+    fn dispatchers_sending_the_same_headers_share_outputs() {
+        let store = MemoryStore::new();
+        greeting(&store, &headers("a")).put(Format::Json, b"\"hi\"".to_vec());
+
+        let cached: Option<String> = greeting(&store, &headers("a")).get();
+
+        assert_eq!(cached.as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn dispatchers_sending_other_headers_do_not_share_outputs() {
+        let store = MemoryStore::new();
+        greeting(&store, &headers("a")).put(Format::Json, b"\"hi\"".to_vec());
+
+        assert_eq!(greeting(&store, &headers("b")).get::<String>(), None);
+        assert_eq!(greeting(&store, &[]).get::<String>(), None);
+    }
+
+    #[test]
+    fn inputs_hold_a_digest_of_the_headers_rather_than_the_headers() {
+        let input = input(&headers("secret"), b"{}");
+        let secret: String = b"secret".iter().map(|b| format!("{:02x}", b)).collect();
+
+        assert!(!input.contains(&secret));
+        assert_eq!(input, format!("{:016x}:7b7d", digest(&headers("secret"))));
+        assert_ne!(digest(&headers("secret")), digest(&headers("secreT")));
+    }
+
+    #[test]
+    fn memory_stores_drop_expired_outputs() {
+        let store = MemoryStore::new();
+
+        store.put("greeter", "greet", "a", output(0));
+        assert!(store.get("greeter", "greet", "a").is_none());
+        assert!(store.ops.lock().unwrap().is_empty());
+
+        store.put("greeter", "greet", "a", output(0));
+        store.put("greeter", "other", "b", output(u64::MAX));
+        assert_eq!(store.ops.lock().unwrap().len(), 1);
+    }
+}
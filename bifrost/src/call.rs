@@ -0,0 +1,288 @@
+use crate::dispatcher::Response;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::stream::ItemStream;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::time;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::transport::BoxFuture;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use futures::future::{self, Either};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use futures::stream::{self, StreamExt};
+use std::collections::HashMap;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use std::future::Future;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// Options of a single call, such as `Dispatcher::send_with`.
+#[derive(Clone, Default)]
+pub struct CallOptions {
+    timeout: Option<Duration>,
+    cancellation: Option<Cancellation>,
+}
+
+/// Cancels the calls it is attached to when `cancel` is called, such as when the component that
+/// made them goes away.
+///
+/// A cancelled call resolves to `Response::Cancelled`, and its request is aborted: its future is
+/// dropped natively, and its `fetch` is aborted through an `AbortController` in the browser.
+#[derive(Clone, Default)]
+pub struct Cancellation {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    /// Wakers of the calls waiting for the cancellation, keyed per waiting future so that each
+    /// removes its own once the call ends.
+    wakers: HashMap<u64, Waker>,
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    next_key: u64,
+}
+
+/// Completes once its cancellation is cancelled.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+struct Cancelled<'a> {
+    cancellation: &'a Cancellation,
+    /// Key of the waker of the future, once it has been polled.
+    key: Option<u64>,
+}
+
+/// Reason a call ended before its response was received.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(
+    not(any(feature = "local-native", feature = "local-browser")),
+    allow(dead_code)
+)]
+pub(crate) enum Interrupt {
+    Timeout,
+    Cancelled,
+}
+
+/// Interrupts a call once its timeout elapses or its cancellation is cancelled.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) struct Guard {
+    timeout: Option<BoxFuture<'static, ()>>,
+    cancellation: Option<Cancellation>,
+}
+
+impl CallOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the time after which the call resolves to `Response::Timeout`, overriding the timeout
+    /// of the dispatcher. The deadline is forwarded to heimdall, which stops executing the op once
+    /// it has passed.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Attaches the call to `cancellation`.
+    pub fn with_cancellation(mut self, cancellation: &Cancellation) -> Self {
+        self.cancellation = Some(cancellation.clone());
+        self
+    }
+
+    pub(crate) fn timeout(&self, default: Option<Duration>) -> Option<Duration> {
+        self.timeout.or(default)
+    }
+
+    #[cfg(any(feature = "debug", feature = "local-native"))]
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .map(Cancellation::is_cancelled)
+            .unwrap_or(false)
+    }
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels all calls attached to this cancellation, including those made after it.
+    pub fn cancel(&self) {
+        let wakers = {
+            let mut state = self.state.lock().unwrap();
+            state.cancelled = true;
+            std::mem::take(&mut state.wakers)
+        };
+
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.lock().unwrap().cancelled
+    }
+
+    /// Completes once cancelled.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    fn cancelled(&self) -> Cancelled<'_> {
+        Cancelled {
+            cancellation: self,
+            key: None,
+        }
+    }
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Future for Cancelled<'_> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let mut state = this.cancellation.state.lock().unwrap();
+
+        if state.cancelled {
+            return Poll::Ready(());
+        }
+
+        let key = *this.key.get_or_insert_with(|| {
+            state.next_key += 1;
+            state.next_key
+        });
+
+        match state.wakers.get(&key) {
+            Some(waker) if waker.will_wake(cx.waker()) => {}
+            _ => {
+                state.wakers.insert(key, cx.waker().clone());
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Drop for Cancelled<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key {
+            self.cancellation.state.lock().unwrap().wakers.remove(&key);
+        }
+    }
+}
+
+impl Interrupt {
+    pub(crate) fn response<T, E>(self) -> Response<T, E> {
+        match self {
+            Interrupt::Timeout => Response::Timeout,
+            Interrupt::Cancelled => Response::Cancelled,
+        }
+    }
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Guard {
+    /// Starts the timeout of a call.
+    pub(crate) fn new(options: &CallOptions, timeout: Option<Duration>) -> Self {
+        Guard {
+            timeout: timeout.map(|t| Box::pin(time::sleep(t)) as BoxFuture<'static, ()>),
+            cancellation: options.cancellation.clone(),
+        }
+    }
+
+    /// Runs `future` until it completes or the call is interrupted.
+    pub(crate) async fn run<F>(&mut self, future: F) -> Result<F::Output, Interrupt>
+    where
+        F: Future,
+    {
+        let timeout = async {
+            match self.timeout.as_mut() {
+                Some(timeout) => timeout.await,
+                None => future::pending().await,
+            }
+        };
+
+        let cancelled = async {
+            match &self.cancellation {
+                Some(cancellation) => cancellation.cancelled().await,
+                None => future::pending().await,
+            }
+        };
+
+        futures::pin_mut!(future, timeout, cancelled);
+
+        match future::select(future, future::select(timeout, cancelled)).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right((Either::Left(_), _)) => Err(Interrupt::Timeout),
+            Either::Right((Either::Right(_), _)) => Err(Interrupt::Cancelled),
+        }
+    }
+
+    /// Ends `items` with the interruption of the call, if it is interrupted before they all are
+    /// received.
+    pub(crate) fn stream<T, E>(self, items: ItemStream<T, E>) -> ItemStream<T, E>
+    where
+        T: 'static,
+        E: 'static,
+    {
+        stream::unfold(Some((items, self)), |state| async move {
+            let (mut items, mut guard) = state?;
+
+            match guard.run(items.next()).await {
+                Ok(Some(item)) => Some((item, Some((items, guard)))),
+                Ok(None) => None,
+                Err(interrupt) => Some((interrupt.response(), None)),
+            }
+        })
+        .boxed_local()
+    }
+}
+
+#[cfg(all(test, any(feature = "local-native", feature = "local-browser")))]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::poll;
+
+    fn waiting(cancellation: &Cancellation) -> usize {
+        cancellation.state.lock().unwrap().wakers.len()
+    }
+
+    #[test]
+    fn calls_stop_waiting_for_the_cancellation_once_they_end() {
+        let cancellation = Cancellation::new();
+
+        block_on(async {
+            for _ in 0..3 {
+                let mut cancelled = Box::pin(cancellation.cancelled());
+                assert!(poll!(cancelled.as_mut()).is_pending());
+                assert!(poll!(cancelled.as_mut()).is_pending());
+                assert_eq!(waiting(&cancellation), 1);
+            }
+        });
+
+        assert_eq!(waiting(&cancellation), 0);
+    }
+
+    #[test]
+    fn cancelling_completes_waiting_calls() {
+        let cancellation = Cancellation::new();
+
+        block_on(async {
+            let mut first = Box::pin(cancellation.cancelled());
+            let mut second = Box::pin(cancellation.cancelled());
+            assert!(poll!(first.as_mut()).is_pending());
+            assert!(poll!(second.as_mut()).is_pending());
+            assert_eq!(waiting(&cancellation), 2);
+
+            cancellation.cancel();
+
+            assert!(poll!(first.as_mut()).is_ready());
+            assert!(poll!(second.as_mut()).is_ready());
+            assert!(poll!(Box::pin(cancellation.cancelled())).is_ready());
+        });
+    }
+}
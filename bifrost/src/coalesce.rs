@@ -0,0 +1,277 @@
+use crate::transport::Reply;
+use futures::channel::oneshot;
+use futures::future::{FutureExt, Shared};
+use futures::StreamExt;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+
+/// Requests in flight, keyed on the module they are sent to, their op id and payload, whose
+/// replies are shared with identical requests made in the meantime.
+pub(crate) struct InFlight<E> {
+    calls: Mutex<HashMap<Key, Call<E>>>,
+}
+
+type Key = (String, String, Vec<u8>);
+
+/// Reply of a request in flight, for callers waiting for it.
+type Call<E> = Shared<oneshot::Receiver<Result<Fetched, E>>>;
+
+/// A reply read in full, so that it can be handed to several callers.
+#[derive(Clone)]
+struct Fetched {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Result<Vec<u8>, String>,
+}
+
+/// Removes a request from those in flight once it completes or is dropped, in which case callers
+/// waiting for it send their own request.
+struct Leader<'a, E> {
+    in_flight: &'a InFlight<E>,
+    key: Option<Key>,
+}
+
+impl<E> InFlight<E>
+where
+    E: Clone,
+{
+    pub(crate) fn new() -> Self {
+        InFlight {
+            calls: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Waits for the reply of an identical request in flight if there is one, or sends the
+    /// request with `send` otherwise.
+    pub(crate) async fn join<F>(
+        &self,
+        module: &str,
+        op: &str,
+        payload: &[u8],
+        send: F,
+    ) -> Result<Reply, E>
+    where
+        F: Future<Output = Result<Reply, E>>,
+    {
+        let key = (module.to_string(), op.to_string(), payload.to_vec());
+
+        let waiting = {
+            let mut calls = self.calls.lock().unwrap();
+
+            match calls.get(&key) {
+                Some(call) => Err(call.clone()),
+                None => {
+                    let (sender, receiver) = oneshot::channel();
+                    calls.insert(key.clone(), receiver.shared());
+                    Ok(sender)
+                }
+            }
+        };
+
+        let sender = match waiting {
+            Ok(sender) => sender,
+            Err(call) => {
+                return match call.await {
+                    Ok(fetched) => fetched.map(Fetched::into_reply),
+                    Err(_) => send.await,
+                }
+            }
+        };
+
+        let mut leader = Leader {
+            in_flight: self,
+            key: Some(key),
+        };
+
+        let fetched = match send.await {
+            Ok(reply) => Ok(Fetched::read(reply).await),
+            Err(e) => Err(e),
+        };
+
+        leader.complete();
+        let _ = sender.send(fetched.clone());
+
+        fetched.map(Fetched::into_reply)
+    }
+}
+
+impl Fetched {
+    async fn read(reply: Reply) -> Self {
+        let status = reply.status;
+        let headers = reply.headers.clone();
+
+        Fetched {
+            status,
+            headers,
+            body: reply.bytes().await,
+        }
+    }
+
+    fn into_reply(self) -> Reply {
+        let Fetched {
+            status,
+            headers,
+            body,
+        } = self;
+
+        let body = futures::stream::once(async move { body });
+
+        Reply {
+            status,
+            headers,
+            #[cfg(not(target_arch = "wasm32"))]
+            body: body.boxed(),
+            #[cfg(target_arch = "wasm32")]
+            body: body.boxed_local(),
+        }
+    }
+}
+
+impl<E> Leader<'_, E> {
+    fn complete(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.in_flight.calls.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+impl<E> Drop for Leader<'_, E> {
+    fn drop(&mut self) {
+        self.complete();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::poll;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn reply(body: &str) -> Reply {
+        let fetched = Fetched {
+            status: 200,
+            headers: Vec::new(),
+            body: Ok(body.as_bytes().to_vec()),
+        };
+
+        fetched.into_reply()
+    }
+
+    async fn body(reply: Result<Reply, String>) -> Vec<u8> {
+        reply.unwrap().bytes().await.unwrap()
+    }
+
+    /// Sends a request answered with `result` once `released` fires, counting it in `sends`.
+    async fn send(
+        sends: &AtomicUsize,
+        released: oneshot::Receiver<()>,
+        result: Result<&str, &str>,
+    ) -> Result<Reply, String> {
+        sends.fetch_add(1, Ordering::SeqCst);
+        let _ = released.await;
+        result.map(reply).map_err(str::to_string)
+    }
+
+    /// Sends a request answered with `result` right away, counting it in `sends`.
+    async fn sent(sends: &AtomicUsize, result: Result<&str, &str>) -> Result<Reply, String> {
+        sends.fetch_add(1, Ordering::SeqCst);
+        result.map(reply).map_err(str::to_string)
+    }
+
+    #[test]
+    fn identical_concurrent_requests_are_sent_once() {
+        let in_flight = InFlight::new();
+        let sends = AtomicUsize::new(0);
+        let (release, released) = oneshot::channel();
+
+        let (leader, follower, _) = block_on(async {
+            futures::join!(
+                in_flight.join("m", "op", b"1", send(&sends, released, Ok("42"))),
+                in_flight.join("m", "op", b"1", sent(&sends, Ok("own"))),
+                async { release.send(()) },
+            )
+        });
+
+        assert_eq!(sends.load(Ordering::SeqCst), 1);
+        assert_eq!(block_on(body(leader)), b"42");
+        assert_eq!(block_on(body(follower)), b"42");
+    }
+
+    #[test]
+    fn requests_differing_in_module_op_or_payload_are_sent_on_their_own() {
+        let in_flight = InFlight::new();
+        let sends = AtomicUsize::new(0);
+        let (release, released) = oneshot::channel();
+
+        let (_, module, op, payload, _) = block_on(async {
+            futures::join!(
+                in_flight.join("m", "op", b"1", send(&sends, released, Ok("42"))),
+                in_flight.join("n", "op", b"1", sent(&sends, Ok("module"))),
+                in_flight.join("m", "other", b"1", sent(&sends, Ok("op"))),
+                in_flight.join("m", "op", b"2", sent(&sends, Ok("payload"))),
+                async { release.send(()) },
+            )
+        });
+
+        assert_eq!(sends.load(Ordering::SeqCst), 4);
+        assert_eq!(block_on(body(module)), b"module");
+        assert_eq!(block_on(body(op)), b"op");
+        assert_eq!(block_on(body(payload)), b"payload");
+    }
+
+    #[test]
+    fn errors_are_shared_with_waiting_callers() {
+        let in_flight = InFlight::new();
+        let sends = AtomicUsize::new(0);
+        let (release, released) = oneshot::channel();
+
+        let (leader, follower, _) = block_on(async {
+            futures::join!(
+                in_flight.join("m", "op", b"1", send(&sends, released, Err("down"))),
+                in_flight.join("m", "op", b"1", sent(&sends, Ok("own"))),
+                async { release.send(()) },
+            )
+        });
+
+        assert_eq!(sends.load(Ordering::SeqCst), 1);
+        assert_eq!(leader.err().as_deref(), Some("down"));
+        assert_eq!(follower.err().as_deref(), Some("down"));
+    }
+
+    #[test]
+    fn waiting_callers_send_their_own_request_when_the_leader_is_dropped() {
+        let in_flight = InFlight::new();
+        let sends = AtomicUsize::new(0);
+        let (_release, released) = oneshot::channel();
+
+        block_on(async {
+            let mut leader =
+                Box::pin(in_flight.join("m", "op", b"1", send(&sends, released, Ok("42"))));
+            assert!(poll!(leader.as_mut()).is_pending());
+
+            let mut follower = Box::pin(in_flight.join("m", "op", b"1", sent(&sends, Ok("own"))));
+            assert!(poll!(follower.as_mut()).is_pending());
+
+            drop(leader);
+            assert_eq!(body(follower.await).await, b"own");
+        });
+
+        assert_eq!(sends.load(Ordering::SeqCst), 2);
+        assert!(in_flight.calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn completed_requests_are_not_shared() {
+        let in_flight = InFlight::new();
+        let sends = AtomicUsize::new(0);
+
+        let first = block_on(in_flight.join("m", "op", b"1", sent(&sends, Ok("1"))));
+        let second = block_on(in_flight.join("m", "op", b"1", sent(&sends, Ok("2"))));
+
+        assert_eq!(sends.load(Ordering::SeqCst), 2);
+        assert_eq!(block_on(body(first)), b"1");
+        assert_eq!(block_on(body(second)), b"2");
+    }
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+#[cfg(any(feature = "remote", feature = "debug"))]
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Request header carrying the identity of the caller, set by a trusted proxy in front of heimdall.
+pub const CALLER_HEADER: &str = "x-bifrost-caller";
+
+/// Request header carrying the request deadline, in milliseconds since the unix epoch.
+pub const DEADLINE_HEADER: &str = "x-bifrost-deadline";
+
+/// Request header carrying the request id. Heimdall generates one if it is missing.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Environment variable through which heimdall passes the context to the guest.
+#[cfg(feature = "remote")]
+pub(crate) const CONTEXT_VAR: &str = "BIFROST_CONTEXT";
+
+/// Metadata about the request an op is being executed for.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Context {
+    pub request_id: String,
+    /// Caller identity, as asserted through `CALLER_HEADER` by a proxy that authenticated the
+    /// caller. Heimdall drops the header sent by clients unless it is started with
+    /// `--trust-caller`, in which case it must only be reachable through such a proxy. The debug
+    /// dispatcher takes it as set with `Dispatcher::with_header`.
+    pub caller: Option<String>,
+    /// Time by which the caller expects a response, in milliseconds since the unix epoch.
+    pub deadline: Option<u64>,
+    /// Headers sent by the client.
+    pub headers: HashMap<String, String>,
+}
+
+#[cfg(any(feature = "remote", feature = "debug"))]
+thread_local! {
+    static CURRENT: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+/// Returns the context of the op currently executing.
+#[cfg(any(feature = "remote", feature = "debug"))]
+pub fn context() -> Context {
+    CURRENT.with(|c| c.borrow().clone()).unwrap_or_default()
+}
+
+/// Sets the context of the op about to execute, returning the previous one.
+#[cfg(any(feature = "remote", feature = "debug"))]
+pub(crate) fn set(context: Option<Context>) -> Option<Context> {
+    CURRENT.with(|c| c.replace(context))
+}
@@ -1,103 +1,1097 @@
-use crate::op::Op;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::balancer::Balancer;
+use crate::balancer::Strategy;
+use crate::batch::{Batch, BatchResults, Failure};
+use crate::breaker::{CircuitBreaker, CircuitState};
+use crate::cache::CacheStore;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::cache::Slot;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::call::Guard;
+use crate::call::{CallOptions, Interrupt};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::coalesce::InFlight;
+#[cfg(feature = "debug")]
+use crate::context::{self, Context, CALLER_HEADER, DEADLINE_HEADER, REQUEST_ID_HEADER};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::exchange::{self, Attempt, Handling};
+use crate::format::Format;
+use crate::interceptor::Interceptor;
+#[cfg(feature = "debug")]
+use crate::interceptor::{Received, Sending};
+#[cfg(feature = "debug")]
+use crate::op::Emitter;
+use crate::op::{Op, StreamingOp};
+use crate::pipeline::{Pipeline, PipelineError};
+#[cfg(feature = "debug")]
+use crate::protocol::{self, Frame, Outcome};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::protocol::{Outcome, StageFailure};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::retry::Retries;
+use crate::retry::RetryPolicy;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::stream;
+use crate::stream::ItemStream;
+use crate::time;
+#[cfg(feature = "local-wasm")]
+use crate::transport::in_process::InProcessTransport;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::transport::{Reply, Request, Transport};
+#[cfg(feature = "local-wasm")]
+use crate::wasm::{self, WasmModule};
 use serde::de::DeserializeOwned;
 use serde::Serialize;
+#[cfg(feature = "debug")]
+use std::collections::HashMap;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use std::future::Future;
+#[cfg(feature = "debug")]
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Request header naming the op carried in the request body.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) const OP_HEADER: &str = "x-bifrost-op";
+
+/// Response header set by heimdall to describe a failed execution.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) const ERROR_HEADER: &str = "x-bifrost-error";
+
+/// `ERROR_HEADER` value for errors returned by the op itself.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) const OP_ERROR: &str = "op";
+
+/// Source of request ids for ops executed locally.
+#[cfg(feature = "debug")]
+static REQUEST_IDS: AtomicU64 = AtomicU64::new(0);
 
 pub struct Dispatcher {
-    url: String,
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    transport: Arc<dyn Transport>,
+    /// Path heimdall's routes are under, empty unless heimdall is served under a prefix.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    base: String,
+    /// Module ops are sent to, unless they declare their own.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    module: String,
+    headers: Vec<(String, String)>,
+    format: Format,
+    #[cfg_attr(
+        not(any(feature = "local-native", feature = "local-browser")),
+        allow(dead_code)
+    )]
+    retry: Option<RetryPolicy>,
+    timeout: Option<Duration>,
+    breaker: Option<CircuitBreaker>,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+    cache: Option<Arc<dyn CacheStore>>,
+    #[cfg_attr(
+        not(any(feature = "local-native", feature = "local-browser")),
+        allow(dead_code)
+    )]
+    coalescing: bool,
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    in_flight: InFlight<PostError>,
 }
 
-#[derive(Debug)]
-pub enum Response<T> {
+#[derive(Clone, Debug)]
+pub enum Response<T, E> {
     Success(T),
+    OpError(E),
     NetworkError(String),
     RequestError(u16, String),
     ParseError(String),
+    /// The call did not complete within its timeout.
+    Timeout,
+    /// The call was cancelled through its `Cancellation`.
+    Cancelled,
+    /// The call was not sent, as the circuit breaker of the dispatcher is open.
+    CircuitOpen,
+}
+
+/// Reason a request did not get a reply.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+#[derive(Clone)]
+enum PostError {
+    Network(String),
+    CircuitOpen,
 }
 
-#[cfg(feature = "local-native")]
 impl Dispatcher {
+    /// Adds a header to every request, exposed to ops through `Context::headers`.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Sets the format ops and their results are serialized in, JSON by default.
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Retries failed requests for idempotent ops according to `policy`. Ops executed locally in
+    /// debug mode are never retried.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Sets the time after which calls resolve to `Response::Timeout`, unless overridden with
+    /// `CallOptions::with_timeout`. Ops executed locally in debug mode are not interrupted, but see
+    /// the deadline in their context.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Guards requests with `breaker`, which keeps a circuit per module and can be cloned
+    /// beforehand to be shared with other dispatchers. Ops executed locally in debug mode are not
+    /// guarded.
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = Some(breaker);
+        self
+    }
+
+    /// Adds an interceptor, called before each request is sent and once its reply is received.
+    /// Interceptors are called in the order they were added before sending, and in reverse order
+    /// after receiving.
+    pub fn with_interceptor<I>(mut self, interceptor: I) -> Self
+    where
+        I: Interceptor + 'static,
+    {
+        self.interceptors.push(Arc::new(interceptor));
+        self
+    }
+
+    /// Caches outputs of ops declaring a `CACHE_TTL` in `store`, keyed on the module executing the
+    /// op, the op id and its serialized input. Only single ops sent to heimdall are cached, not
+    /// batches, pipelines, streams or ops executed locally in debug mode.
+    pub fn with_cache<S>(mut self, store: S) -> Self
+    where
+        S: CacheStore + 'static,
+    {
+        self.cache = Some(Arc::new(store));
+        self
+    }
+
+    /// Shares a single request among identical calls in flight at the same time, that is calls of
+    /// the same idempotent op of the same module with byte-identical inputs. Calls are only shared
+    /// while the request is in flight: calls made once it completed send their own.
+    pub fn with_coalescing(mut self) -> Self {
+        self.coalescing = true;
+        self
+    }
+
+    /// Creates an empty batch of ops to send with `send_batch`.
+    pub fn batch(&self) -> Batch {
+        Batch::new(self.format, self.module_id())
+    }
+
+    /// Starts a pipeline of ops to send with `send_pipeline`.
+    pub fn pipeline<T>(&self, op: &T) -> Pipeline<T>
+    where
+        T: Op + Serialize + DeserializeOwned,
+        T::Output: Serialize,
+        T::Error: Serialize,
+    {
+        Pipeline::new(op, self.module_id())
+    }
+
+    /// Deadline of a call, in milliseconds since the unix epoch.
+    fn deadline(&self, options: &CallOptions) -> Option<u64> {
+        options
+            .timeout(self.timeout)
+            .map(|timeout| time::now_millis() + timeout.as_millis() as u64)
+    }
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl Dispatcher {
+    /// Creates a dispatcher for the heimdall execute route at `url`, such as
+    /// `http://localhost:8080/my-module/execute`, sending requests over HTTP.
     pub fn create(url: String) -> Self {
-        Dispatcher { url }
+        let (origin, base, module) = split_url(&url);
+        Self::over_http(origin, base, module)
+    }
+
+    /// Creates a dispatcher for the heimdall instance at `base_url`, such as
+    /// `http://localhost:8080`, sending ops to the module `module_id` unless they declare their
+    /// own module with `Op::MODULE`.
+    pub fn for_module(base_url: String, module_id: &str) -> Self {
+        let (origin, base) = split_origin(&base_url);
+        Self::over_http(origin, base, module_id)
+    }
+
+    /// Creates a dispatcher spreading requests across the heimdall instances at `base_urls`
+    /// according to `strategy`, sending ops to the module `module_id` unless they declare their
+    /// own. See `Balancer` for how failing instances are handled, and `with_transport` to balance
+    /// requests across other transports.
+    pub fn for_endpoints(base_urls: Vec<String>, module_id: &str, strategy: Strategy) -> Self {
+        let balancer = base_urls
+            .iter()
+            .fold(Balancer::new(strategy), |balancer, url| {
+                balancer.with_url(url)
+            });
+
+        Self::with_transport(balancer, module_id)
+    }
+
+    fn over_http(origin: &str, base: &str, module: &str) -> Self {
+        #[cfg(feature = "local-native")]
+        let transport = crate::transport::http::HttpTransport::new(origin);
+        #[cfg(not(feature = "local-native"))]
+        let transport = crate::transport::fetch::FetchTransport::new(origin);
+
+        Dispatcher {
+            transport: Arc::new(transport),
+            base: base.to_string(),
+            module: module.to_string(),
+            headers: Vec::new(),
+            format: Format::default(),
+            retry: None,
+            timeout: None,
+            breaker: None,
+            interceptors: Vec::new(),
+            cache: None,
+            coalescing: false,
+            in_flight: InFlight::new(),
+        }
     }
 
-    pub async fn send<T>(&self, op: &T) -> Response<T::Output>
+    /// Creates a dispatcher for the module `module_id`, reached through `transport`, to which ops
+    /// not declaring their own module are sent.
+    pub fn with_transport<T>(transport: T, module_id: &str) -> Self
+    where
+        T: Transport + 'static,
+    {
+        Dispatcher {
+            transport: Arc::new(transport),
+            base: String::new(),
+            module: module_id.to_string(),
+            headers: Vec::new(),
+            format: Format::default(),
+            retry: None,
+            timeout: None,
+            breaker: None,
+            interceptors: Vec::new(),
+            cache: None,
+            coalescing: false,
+            in_flight: InFlight::new(),
+        }
+    }
+
+    /// Module executing ops that do not declare their own.
+    fn module_id(&self) -> Option<&str> {
+        Some(&self.module)
+    }
+
+    /// State of the circuit of the module of the dispatcher, if it has a circuit breaker. See
+    /// `CircuitBreaker::state` for the circuits of other modules.
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.breaker
+            .as_ref()
+            .map(|breaker| breaker.state(&self.module))
+    }
+
+    /// Removes all cached outputs of the op `T`, executed by its own module or the module of the
+    /// dispatcher, such as after an op modifying the data it reads.
+    pub fn invalidate<T: Op>(&self) {
+        if let Some(cache) = &self.cache {
+            cache.invalidate(T::MODULE.unwrap_or(&self.module), T::id());
+        }
+    }
+
+    /// Creates a dispatcher executing ops in the compiled guest `modules` within the current
+    /// process instead of on a heimdall instance, sending them to the module `module_id` unless
+    /// they declare their own. This exercises the actual `wasm32-wasi` executable of an
+    /// application, such as in integration tests, without running heimdall.
+    ///
+    /// Fails if a module does not compile, has a malformed manifest, or if one of its capabilities
+    /// is invalid.
+    #[cfg(feature = "local-wasm")]
+    pub fn from_wasm(modules: Vec<WasmModule>, module_id: &str) -> Result<Self, String> {
+        let registry = wasm::registry(modules)?;
+        Ok(Self::with_transport(
+            InProcessTransport::new(registry),
+            module_id,
+        ))
+    }
+
+    pub async fn send<T>(&self, op: &T) -> Response<T::Output, T::Error>
     where
         T: Op + Serialize,
         T::Output: DeserializeOwned,
+        T::Error: DeserializeOwned,
     {
-        let response = reqwest::Client::new()
-            .post(&self.url)
-            .json(&(<T as Op>::id(), op))
-            .send()
+        self.send_with(op, &CallOptions::default()).await
+    }
+
+    /// Sends an op, with a timeout or cancellation specific to this call.
+    pub async fn send_with<T>(&self, op: &T, options: &CallOptions) -> Response<T::Output, T::Error>
+    where
+        T: Op + Serialize,
+        T::Output: DeserializeOwned,
+        T::Error: DeserializeOwned,
+    {
+        self.guarded(options, |deadline| self.execute(op, deadline))
             .await
-            .map_err(|e| e.to_string());
-
-        match response {
-            Err(e) => Response::NetworkError(e),
-            Ok(resp) => {
-                if resp.status().is_success() {
-                    match resp.json::<T::Output>().await {
-                        Ok(v) => Response::Success(v),
-                        Err(e) => Response::ParseError(e.to_string()),
-                    }
-                } else {
-                    Response::RequestError(
-                        resp.status().as_u16(),
-                        resp.text().await.ok().unwrap_or("".to_string()),
-                    )
-                }
-            }
+            .unwrap_or_else(Interrupt::response)
+    }
+
+    /// Sends all ops of a batch in a single request, executed in order by one guest instance.
+    pub async fn send_batch(&self, batch: &Batch) -> BatchResults {
+        self.send_batch_with(batch, &CallOptions::default()).await
+    }
+
+    /// Sends a batch, with a timeout or cancellation specific to this call.
+    pub async fn send_batch_with(&self, batch: &Batch, options: &CallOptions) -> BatchResults {
+        self.guarded(options, |deadline| self.execute_batch(batch, deadline))
+            .await
+            .unwrap_or_else(|interrupt| batch.failed(Failure::Interrupted(interrupt)))
+    }
+
+    /// Sends a pipeline in a single request, returning the output of its last step or the first
+    /// failed step.
+    pub async fn send_pipeline<T>(
+        &self,
+        pipeline: &Pipeline<T>,
+    ) -> Response<T::Output, PipelineError>
+    where
+        T: Op,
+        T::Output: DeserializeOwned,
+    {
+        self.send_pipeline_with(pipeline, &CallOptions::default())
+            .await
+    }
+
+    /// Sends a pipeline, with a timeout or cancellation specific to this call.
+    pub async fn send_pipeline_with<T>(
+        &self,
+        pipeline: &Pipeline<T>,
+        options: &CallOptions,
+    ) -> Response<T::Output, PipelineError>
+    where
+        T: Op,
+        T::Output: DeserializeOwned,
+    {
+        self.guarded(options, |deadline| {
+            self.execute_pipeline(pipeline, deadline)
+        })
+        .await
+        .unwrap_or_else(Interrupt::response)
+    }
+
+    /// Sends a streaming op, whose items are yielded as heimdall receives them.
+    pub async fn send_stream<T>(&self, op: &T) -> ItemStream<T::Item, T::Error>
+    where
+        T: StreamingOp + Serialize,
+        T::Item: DeserializeOwned + 'static,
+        T::Error: DeserializeOwned + 'static,
+    {
+        self.send_stream_with(op, &CallOptions::default()).await
+    }
+
+    /// Sends a streaming op, with a timeout or cancellation specific to this call, which covers
+    /// receiving all of its items.
+    pub async fn send_stream_with<T>(
+        &self,
+        op: &T,
+        options: &CallOptions,
+    ) -> ItemStream<T::Item, T::Error>
+    where
+        T: StreamingOp + Serialize,
+        T::Item: DeserializeOwned + 'static,
+        T::Error: DeserializeOwned + 'static,
+    {
+        let deadline = self.deadline(options);
+        let mut guard = Guard::new(options, options.timeout(self.timeout));
+
+        match guard.run(self.execute_stream(op, deadline)).await {
+            Ok(items) => guard.stream(items),
+            Err(interrupt) => stream::once(interrupt.response()),
         }
     }
-}
 
-#[cfg(feature = "local-browser")]
-impl Dispatcher {
-    pub fn create(url: String) -> Self {
-        Dispatcher { url }
+    /// Runs a call until it completes or is interrupted, giving it its deadline.
+    async fn guarded<'a, F>(
+        &self,
+        options: &CallOptions,
+        call: impl FnOnce(Option<u64>) -> F,
+    ) -> Result<F::Output, Interrupt>
+    where
+        F: Future + 'a,
+    {
+        let deadline = self.deadline(options);
+        let mut guard = Guard::new(options, options.timeout(self.timeout));
+
+        guard.run(call(deadline)).await
     }
 
-    pub async fn send<T>(&self, op: &T) -> Response<T::Output>
+    async fn execute<T>(&self, op: &T, deadline: Option<u64>) -> Response<T::Output, T::Error>
     where
         T: Op + Serialize,
         T::Output: DeserializeOwned,
+        T::Error: DeserializeOwned,
     {
-        let response = gloo_net::http::Request::post(&self.url)
-            .json(&(<T as Op>::id(), op))
-            .expect("can serialize payload")
-            .send()
-            .await;
-
-        match response {
-            Err(e) => Response::NetworkError(e.to_string()),
-            Ok(resp) => {
-                if resp.status() == 200 {
-                    match resp.json::<T::Output>().await {
-                        Ok(v) => Response::Success(v),
-                        Err(e) => Response::ParseError(e.to_string()),
+        let body = match self.format.serialize(op) {
+            Ok(body) => body,
+            Err(e) => return Response::ParseError(e),
+        };
+
+        let module = <T as Op>::MODULE.unwrap_or(&self.module);
+        let slot = Slot::new::<T>(self.cache.as_deref(), module, &self.headers, &body);
+
+        if let Some(output) = slot.as_ref().and_then(Slot::get) {
+            return Response::Success(output);
+        }
+
+        let coalesced = self.coalescing && T::IDEMPOTENT;
+        let payload = if coalesced { body.clone() } else { Vec::new() };
+
+        let post = self.post(
+            <T as Op>::MODULE,
+            "execute",
+            Some(<T as Op>::id()),
+            body,
+            T::IDEMPOTENT,
+            deadline,
+        );
+
+        let result = if coalesced {
+            self.in_flight.join(module, T::id(), &payload, post).await
+        } else {
+            post.await
+        };
+
+        let reply = match result {
+            Ok(reply) => reply,
+            Err(e) => return e.response(),
+        };
+
+        let format = reply.head().format(self.format);
+
+        match reply.head().handling() {
+            Handling::Executed => {
+                let bytes = match reply.bytes().await {
+                    Ok(bytes) => bytes,
+                    Err(e) => return Response::ParseError(e),
+                };
+
+                match format.deserialize(&bytes) {
+                    Ok(v) => {
+                        if let Some(slot) = &slot {
+                            slot.put(format, bytes);
+                        }
+
+                        Response::Success(v)
                     }
-                } else {
-                    Response::RequestError(
-                        resp.status(),
-                        resp.text().await.ok().unwrap_or("".to_string()),
-                    )
+                    Err(e) => Response::ParseError(e),
+                }
+            }
+            Handling::OpError => match reply.bytes().await.and_then(|b| format.deserialize(&b)) {
+                Ok(e) => Response::OpError(e),
+                Err(e) => Response::ParseError(e),
+            },
+            _ => rejected(reply).await,
+        }
+    }
+
+    async fn execute_batch(&self, batch: &Batch, deadline: Option<u64>) -> BatchResults {
+        let body = match self.format.serialize(&batch.calls()) {
+            Ok(body) => body,
+            Err(e) => return batch.failed(Failure::Parse(e)),
+        };
+
+        let reply = match self
+            .post(
+                batch.module(),
+                "batch",
+                None,
+                body,
+                batch.is_idempotent(),
+                deadline,
+            )
+            .await
+        {
+            Ok(reply) => reply,
+            Err(PostError::Network(e)) => return batch.failed(Failure::Network(e)),
+            Err(PostError::CircuitOpen) => return batch.failed(Failure::CircuitOpen),
+        };
+
+        let format = reply.head().format(self.format);
+
+        match reply.head().handling() {
+            Handling::Executed => {
+                let bytes = reply.bytes().await;
+                match bytes.and_then(|b| format.deserialize::<Vec<Outcome>>(&b)) {
+                    Ok(outcomes) => batch.results(format, outcomes),
+                    Err(e) => batch.failed(Failure::Parse(e)),
                 }
+            }
+            Handling::DeadlineExceeded => batch.failed(Failure::Interrupted(Interrupt::Timeout)),
+            _ => batch.failed(Failure::Request(reply.status, reply.text().await)),
+        }
+    }
+
+    async fn execute_pipeline<T>(
+        &self,
+        pipeline: &Pipeline<T>,
+        deadline: Option<u64>,
+    ) -> Response<T::Output, PipelineError>
+    where
+        T: Op,
+        T::Output: DeserializeOwned,
+    {
+        let body = match pipeline.stages().and_then(|s| self.format.serialize(&s)) {
+            Ok(body) => body,
+            Err(e) => return Response::ParseError(e),
+        };
+
+        let reply = match self
+            .post(
+                pipeline.module(),
+                "pipeline",
+                None,
+                body,
+                pipeline.is_idempotent(),
+                deadline,
+            )
+            .await
+        {
+            Ok(reply) => reply,
+            Err(e) => return e.response(),
+        };
+
+        let format = reply.head().format(self.format);
+
+        match reply.head().handling() {
+            Handling::Executed => match reply.bytes().await.and_then(|b| format.deserialize(&b)) {
+                Ok(v) => Response::Success(v),
+                Err(e) => Response::ParseError(e),
             },
+            Handling::OpError => {
+                let bytes = reply.bytes().await;
+                match bytes.and_then(|b| format.deserialize::<StageFailure>(&b)) {
+                    Ok(failure) => pipeline.failed(format, failure),
+                    Err(e) => Response::ParseError(e),
+                }
+            }
+            _ => rejected(reply).await,
+        }
+    }
+
+    async fn execute_stream<T>(
+        &self,
+        op: &T,
+        deadline: Option<u64>,
+    ) -> ItemStream<T::Item, T::Error>
+    where
+        T: StreamingOp + Serialize,
+        T::Item: DeserializeOwned + 'static,
+        T::Error: DeserializeOwned + 'static,
+    {
+        let body = match self.format.serialize(op) {
+            Ok(body) => body,
+            Err(e) => return stream::once(Response::ParseError(e)),
+        };
+
+        let reply = match self
+            .post(
+                <T as StreamingOp>::MODULE,
+                "stream",
+                Some(<T as StreamingOp>::id()),
+                body,
+                T::IDEMPOTENT,
+                deadline,
+            )
+            .await
+        {
+            Ok(reply) => reply,
+            Err(e) => return stream::once(e.response()),
+        };
+
+        match reply.head().handling() {
+            Handling::Executed => stream::decode(reply.body),
+            _ => stream::once(rejected(reply).await),
+        }
+    }
+
+    /// Posts a request to a route of `module`, or of the module of the dispatcher, retrying it
+    /// according to the retry policy if `idempotent`.
+    async fn post(
+        &self,
+        module: Option<&str>,
+        route: &str,
+        op: Option<&str>,
+        body: Vec<u8>,
+        idempotent: bool,
+        deadline: Option<u64>,
+    ) -> Result<Reply, PostError> {
+        let module = module.unwrap_or(&self.module);
+        let request = Request {
+            path: exchange::path(&self.base, module, route),
+            headers: exchange::headers(self.format, route, op, deadline, &self.headers),
+            body,
+        };
+
+        let mut retries = Retries::new(self.retry.as_ref(), idempotent, deadline);
+
+        if !retries.is_enabled() {
+            return self.attempt(module, route, op, request).await;
+        }
+
+        loop {
+            let result = self.attempt(module, route, op, request.clone()).await;
+
+            let backoff = match &result {
+                Ok(reply) => retries.after(Some(reply.head())),
+                Err(PostError::Network(_)) => retries.after(None),
+                Err(PostError::CircuitOpen) => None,
+            };
+
+            match backoff {
+                Some(backoff) => time::sleep(backoff).await,
+                None => return result,
+            }
+        }
+    }
+
+    /// Sends a request to `module` once, through the circuit breaker if there is one and the
+    /// interceptors.
+    async fn attempt(
+        &self,
+        module: &str,
+        route: &str,
+        op: Option<&str>,
+        mut request: Request,
+    ) -> Result<Reply, PostError> {
+        let attempt = Attempt::start(
+            self.breaker.as_ref(),
+            &self.interceptors,
+            module,
+            route,
+            op,
+            &request.body,
+            &mut request.headers,
+        )
+        .ok_or(PostError::CircuitOpen)?;
+
+        let result = self.transport.send(request).await;
+        attempt.end(result.as_ref().map(Reply::head).map_err(String::as_str));
+
+        result.map_err(PostError::Network)
+    }
+
+    /// Format ops are serialized in.
+    #[cfg(feature = "local-browser")]
+    pub(crate) fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Sends an op serialized in the format of the dispatcher, leaving its output or error
+    /// serialized in the returned format. Used to replay ops queued by an `OfflineQueue`.
+    #[cfg(feature = "local-browser")]
+    pub(crate) async fn send_serialized(
+        &self,
+        module: Option<&str>,
+        op: &str,
+        payload: Vec<u8>,
+    ) -> (Format, Response<Vec<u8>, Vec<u8>>) {
+        let call = |deadline| async move {
+            let reply = match self
+                .post(module, "execute", Some(op), payload, false, deadline)
+                .await
+            {
+                Ok(reply) => reply,
+                Err(e) => return (self.format, e.response()),
+            };
+
+            let format = reply.head().format(self.format);
+
+            let response = match reply.head().handling() {
+                Handling::Executed => match reply.bytes().await {
+                    Ok(bytes) => Response::Success(bytes),
+                    Err(e) => Response::ParseError(e),
+                },
+                Handling::OpError => match reply.bytes().await {
+                    Ok(bytes) => Response::OpError(bytes),
+                    Err(e) => Response::ParseError(e),
+                },
+                _ => rejected(reply).await,
+            };
+
+            (format, response)
+        };
+
+        match self.guarded(&CallOptions::new(), call).await {
+            Ok(delivery) => delivery,
+            Err(interrupt) => (self.format, interrupt.response()),
         }
     }
 }
 
+/// Splits the URL of a heimdall execute route, such as `http://localhost:8080/my-module/execute`,
+/// into its origin, the path heimdall's routes are under and the module id.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) fn split_url(url: &str) -> (&str, &str, &str) {
+    let (origin, path) = split_origin(url);
+    let path = path.strip_suffix("/execute").unwrap_or(path);
+    let (base, module) = path.rsplit_once('/').unwrap_or(("", path));
+
+    (origin, base, module)
+}
+
+/// Splits a URL into its origin and its path, without a trailing slash.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) fn split_origin(url: &str) -> (&str, &str) {
+    let start = url.find("://").map(|i| i + 3).unwrap_or(0);
+    let (origin, path) = match url[start..].find('/') {
+        Some(i) => url.split_at(start + i),
+        None => (url, ""),
+    };
+
+    (origin, path.trim_end_matches('/'))
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl PostError {
+    fn response<T, E>(self) -> Response<T, E> {
+        match self {
+            PostError::Network(e) => Response::NetworkError(e),
+            PostError::CircuitOpen => Response::CircuitOpen,
+        }
+    }
+}
+
+/// Response to a request heimdall failed to execute.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+async fn rejected<T, E>(reply: Reply) -> Response<T, E> {
+    match reply.head().handling() {
+        Handling::DeadlineExceeded => Response::Timeout,
+        _ => Response::RequestError(reply.status, reply.text().await),
+    }
+}
+
 #[cfg(feature = "debug")]
 impl Dispatcher {
-    pub fn create(url: String) -> Self {
-        Dispatcher { url }
+    pub fn create(_url: String) -> Self {
+        Dispatcher {
+            headers: Vec::new(),
+            format: Format::default(),
+            retry: None,
+            timeout: None,
+            breaker: None,
+            interceptors: Vec::new(),
+            cache: None,
+            coalescing: false,
+        }
+    }
+
+    /// Ops are executed locally whatever their module.
+    fn module_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Ops executed locally are never guarded, so the circuit is always closed.
+    pub fn circuit_state(&self) -> Option<CircuitState> {
+        self.breaker.as_ref().map(|_| CircuitState::Closed)
     }
 
-    pub async fn send<T>(&self, op: &T) -> Response<T::Output>
+    /// Ops executed locally are never cached, so there is nothing to invalidate.
+    pub fn invalidate<T: Op>(&self) {}
+
+    /// Ops are executed locally whatever their module, as with `create`.
+    pub fn for_module(_base_url: String, _module_id: &str) -> Self {
+        Self::create(String::new())
+    }
+
+    /// Ops are executed locally, as with `create`.
+    pub fn for_endpoints(_base_urls: Vec<String>, _module_id: &str, _strategy: Strategy) -> Self {
+        Self::create(String::new())
+    }
+
+    pub async fn send<T>(&self, op: &T) -> Response<T::Output, T::Error>
     where
-        T: Op + Serialize,
+        T: Op + Serialize + DeserializeOwned,
+        T::Output: Serialize + DeserializeOwned,
+        T::Error: Serialize + DeserializeOwned,
+    {
+        self.send_with(op, &CallOptions::default()).await
+    }
+
+    /// Executes an op in place, unless the call is already cancelled.
+    ///
+    /// The op is passed through the format of the dispatcher as it would be remotely: it is
+    /// serialized and deserialized back before being executed, and so is its output or error.
+    /// Types that do not survive the round trip are reported as `Response::ParseError`.
+    pub async fn send_with<T>(&self, op: &T, options: &CallOptions) -> Response<T::Output, T::Error>
+    where
+        T: Op + Serialize + DeserializeOwned,
+        T::Output: Serialize + DeserializeOwned,
+        T::Error: Serialize + DeserializeOwned,
+    {
+        if options.is_cancelled() {
+            return Response::Cancelled;
+        }
+
+        let payload = match self.format.serialize(op) {
+            Ok(payload) => payload,
+            Err(e) => return Response::ParseError(e),
+        };
+
+        self.intercepted("execute", Some(<T as Op>::id()), &payload, options, || {
+            match protocol::exec::<T>(&payload, self.format, self.format) {
+                Outcome::Success(bytes) => match self.format.deserialize(&bytes) {
+                    Ok(v) => (Response::Success(v), 200),
+                    Err(e) => (Response::ParseError(round_trip("output", e)), 200),
+                },
+                Outcome::OpError(bytes) => match self.format.deserialize(&bytes) {
+                    Ok(e) => (Response::OpError(e), 422),
+                    Err(e) => (Response::ParseError(round_trip("error", e)), 422),
+                },
+                Outcome::InputDecodeError(e) => (Response::ParseError(round_trip("op", e)), 400),
+                Outcome::OutputEncodeError(e) | Outcome::UnknownOp(e) => {
+                    (Response::ParseError(e), 500)
+                }
+            }
+        })
+    }
+
+    pub async fn send_batch(&self, batch: &Batch) -> BatchResults {
+        self.send_batch_with(batch, &CallOptions::default()).await
+    }
+
+    /// Executes the ops of a batch in order, sharing a single context as they would remotely.
+    pub async fn send_batch_with(&self, batch: &Batch, options: &CallOptions) -> BatchResults {
+        if options.is_cancelled() {
+            return batch.failed(Failure::Interrupted(Interrupt::Cancelled));
+        }
+
+        let payload = match self.format.serialize(&batch.calls()) {
+            Ok(payload) => payload,
+            Err(e) => return batch.failed(Failure::Parse(e)),
+        };
+
+        let outcomes = self.intercepted("batch", None, &payload, options, || (batch.exec(), 200));
+
+        batch.results(self.format, outcomes)
+    }
+
+    pub async fn send_pipeline<T>(
+        &self,
+        pipeline: &Pipeline<T>,
+    ) -> Response<T::Output, PipelineError>
+    where
+        T: Op,
+        T::Output: DeserializeOwned,
+    {
+        self.send_pipeline_with(pipeline, &CallOptions::default())
+            .await
+    }
+
+    /// Runs a pipeline in place, as a guest would.
+    pub async fn send_pipeline_with<T>(
+        &self,
+        pipeline: &Pipeline<T>,
+        options: &CallOptions,
+    ) -> Response<T::Output, PipelineError>
+    where
+        T: Op,
         T::Output: DeserializeOwned,
     {
-        Response::Success(op.execute())
+        if options.is_cancelled() {
+            return Response::Cancelled;
+        }
+
+        let stages = match pipeline.stages() {
+            Ok(stages) => stages,
+            Err(e) => return Response::ParseError(e),
+        };
+
+        let payload = match self.format.serialize(&stages) {
+            Ok(payload) => payload,
+            Err(e) => return Response::ParseError(e),
+        };
+
+        let result =
+            self.intercepted(
+                "pipeline",
+                None,
+                &payload,
+                options,
+                || match protocol::run_pipeline(&stages, |op| pipeline.handler(op), self.format) {
+                    Ok(bytes) => (Ok(bytes), 200),
+                    Err(failure) => (Err(failure), 422),
+                },
+            );
+
+        match result {
+            Ok(bytes) => match self.format.deserialize(&bytes) {
+                Ok(v) => Response::Success(v),
+                Err(e) => Response::ParseError(e),
+            },
+            Err(failure) => pipeline.failed(self.format, failure),
+        }
+    }
+
+    pub async fn send_stream<T>(&self, op: &T) -> ItemStream<T::Item, T::Error>
+    where
+        T: StreamingOp + Serialize + DeserializeOwned,
+        T::Item: Serialize + DeserializeOwned + 'static,
+        T::Error: Serialize + DeserializeOwned + 'static,
+    {
+        self.send_stream_with(op, &CallOptions::default()).await
+    }
+
+    /// Executes a streaming op in place, yielding its items once it completes.
+    ///
+    /// As with `send_with`, the op is serialized and deserialized back before being executed, and
+    /// its items and error are passed through the frames they would be streamed in.
+    pub async fn send_stream_with<T>(
+        &self,
+        op: &T,
+        options: &CallOptions,
+    ) -> ItemStream<T::Item, T::Error>
+    where
+        T: StreamingOp + Serialize + DeserializeOwned,
+        T::Item: Serialize + DeserializeOwned + 'static,
+        T::Error: Serialize + DeserializeOwned + 'static,
+    {
+        use futures::StreamExt;
+
+        if options.is_cancelled() {
+            return futures::stream::iter([Response::Cancelled]).boxed_local();
+        }
+
+        let payload = match self.format.serialize(op) {
+            Ok(payload) => payload,
+            Err(e) => return futures::stream::iter([Response::ParseError(e)]).boxed_local(),
+        };
+
+        let mut items = Vec::new();
+
+        self.intercepted(
+            "stream",
+            Some(<T as StreamingOp>::id()),
+            &payload,
+            options,
+            || {
+                let op: T = match self.format.deserialize(&payload) {
+                    Ok(op) => op,
+                    Err(e) => return (items.push(Response::ParseError(round_trip("op", e))), 400),
+                };
+
+                let mut emit = |item| items.push(framed(Frame::Item(item)));
+
+                if let Err(e) = op.execute(&mut Emitter::new(&mut emit)) {
+                    items.push(framed(Frame::Error(e)));
+                }
+
+                ((), 200)
+            },
+        );
+
+        // Streams end after their first error, which may come from the round trip of an item.
+        if let Some(end) = items
+            .iter()
+            .position(|r| !matches!(r, Response::Success(_)))
+        {
+            items.truncate(end + 1);
+        }
+
+        futures::stream::iter(items).boxed_local()
+    }
+
+    /// Runs `execute` as heimdall would run a request to `route`, through the interceptors.
+    /// `execute` returns its result along with the status heimdall would reply with.
+    fn intercepted<R>(
+        &self,
+        route: &str,
+        op: Option<&str>,
+        payload: &[u8],
+        options: &CallOptions,
+        execute: impl FnOnce() -> (R, u16),
+    ) -> R {
+        let mut headers = self.headers.clone();
+
+        let mut sending = Sending {
+            route,
+            op,
+            payload,
+            headers: &mut headers,
+        };
+
+        for interceptor in self.interceptors.iter() {
+            interceptor.before_send(&mut sending);
+        }
+
+        let start = time::now_millis();
+
+        let previous = context::set(Some(self.context(&headers, options)));
+        let (result, status) = execute();
+        context::set(previous);
+
+        let received = Received {
+            route,
+            op,
+            status: Some(status),
+            headers: &[],
+            error: None,
+            elapsed: Duration::from_millis(time::now_millis().saturating_sub(start)),
+        };
+
+        for interceptor in self.interceptors.iter().rev() {
+            interceptor.after_receive(&received);
+        }
+
+        result
+    }
+
+    fn context(&self, headers: &[(String, String)], options: &CallOptions) -> Context {
+        let headers: HashMap<String, String> = headers.iter().cloned().collect();
+
+        Context {
+            request_id: headers
+                .get(REQUEST_ID_HEADER)
+                .cloned()
+                .unwrap_or_else(|| format!("debug-{}", REQUEST_IDS.fetch_add(1, Ordering::SeqCst))),
+            caller: headers.get(CALLER_HEADER).cloned(),
+            deadline: headers
+                .get(DEADLINE_HEADER)
+                .and_then(|d| d.parse().ok())
+                .or_else(|| self.deadline(options)),
+            headers,
+        }
+    }
+}
+
+/// Error of a value that could not be passed through the format of a debug dispatcher.
+#[cfg(feature = "debug")]
+fn round_trip(value: &str, error: String) -> String {
+    format!("{} does not round trip: {}", value, error)
+}
+
+/// Passes an item or error of a streaming op through the frame it would be streamed in.
+#[cfg(feature = "debug")]
+fn framed<T, E>(frame: Frame<T, E>) -> Response<T, E>
+where
+    T: Serialize + DeserializeOwned,
+    E: Serialize + DeserializeOwned,
+{
+    let line = match serde_json::to_vec(&frame) {
+        Ok(line) => line,
+        Err(e) => return Response::ParseError(e.to_string()),
+    };
+
+    match serde_json::from_slice(&line) {
+        Ok(Frame::Item(item)) => Response::Success(item),
+        Ok(Frame::Error(e)) => Response::OpError(e),
+        Ok(Frame::Failure {
+            status, message, ..
+        }) => Response::RequestError(status, message),
+        Err(e) => Response::ParseError(round_trip("item", e.to_string())),
     }
 }
@@ -0,0 +1,220 @@
+use crate::breaker::{CircuitBreaker, Permit};
+use crate::context::DEADLINE_HEADER;
+use crate::dispatcher::{ERROR_HEADER, OP_ERROR, OP_HEADER};
+use crate::format::Format;
+use crate::interceptor::{Interceptor, Received, Sending};
+use crate::protocol::{DEADLINE_EXCEEDED, NDJSON};
+use crate::time;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Status and headers of a reply, from which dispatchers tell how heimdall handled a request
+/// whatever they received it with.
+#[derive(Clone, Copy)]
+pub(crate) struct Head<'a> {
+    pub(crate) status: u16,
+    headers: &'a [(String, String)],
+}
+
+/// How heimdall handled a request, as told by its reply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Handling {
+    /// The request was executed, and its body holds the output.
+    Executed,
+    /// The op was executed and returned an error, which the body holds.
+    OpError,
+    /// Heimdall stopped executing the request because its deadline passed.
+    DeadlineExceeded,
+    /// Heimdall failed to execute the request, responding with the given status.
+    Rejected(u16),
+}
+
+/// A single attempt at sending a request, guarded by the circuit breaker of a dispatcher and
+/// observed by its interceptors.
+pub(crate) struct Attempt<'a> {
+    route: &'a str,
+    op: Option<&'a str>,
+    interceptors: &'a [Arc<dyn Interceptor>],
+    permit: Option<Permit<'a>>,
+    start: u64,
+}
+
+/// Path of the route `route` of `module`, for heimdall served under `base`.
+pub(crate) fn path(base: &str, module: &str, route: &str) -> String {
+    format!("{}/{}/{}", base, module, route)
+}
+
+/// Headers of a request to `route` with a body in `format`, naming `op` for routes taking a
+/// single op and forwarding the deadline of the call, followed by the headers of the dispatcher.
+pub(crate) fn headers(
+    format: Format,
+    route: &str,
+    op: Option<&str>,
+    deadline: Option<u64>,
+    extra: &[(String, String)],
+) -> Vec<(String, String)> {
+    // Streams are answered with a frame per line, other routes in the format of the dispatcher.
+    let accept = match route {
+        "stream" => NDJSON,
+        _ => format.content_type(),
+    };
+
+    let mut headers = vec![
+        (
+            "content-type".to_string(),
+            format.content_type().to_string(),
+        ),
+        ("accept".to_string(), accept.to_string()),
+    ];
+
+    if let Some(op) = op {
+        headers.push((OP_HEADER.to_string(), op.to_string()));
+    }
+
+    if let Some(deadline) = deadline {
+        if !extra.iter().any(|(name, _)| name == DEADLINE_HEADER) {
+            headers.push((DEADLINE_HEADER.to_string(), deadline.to_string()));
+        }
+    }
+
+    headers.extend(extra.iter().cloned());
+    headers
+}
+
+impl<'a> Head<'a> {
+    pub(crate) fn new(status: u16, headers: &'a [(String, String)]) -> Self {
+        Head { status, headers }
+    }
+
+    /// Value of a header, whose name is compared case-insensitively.
+    pub(crate) fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub(crate) fn handling(&self) -> Handling {
+        match self.header(ERROR_HEADER) {
+            Some(OP_ERROR) => Handling::OpError,
+            Some(DEADLINE_EXCEEDED) => Handling::DeadlineExceeded,
+            _ if (200..300).contains(&self.status) => Handling::Executed,
+            _ => Handling::Rejected(self.status),
+        }
+    }
+
+    /// Format of the body, as given by its `Content-Type`, or `default` if it has none.
+    pub(crate) fn format(&self, default: Format) -> Format {
+        self.header("content-type")
+            .and_then(Format::from_content_type)
+            .unwrap_or(default)
+    }
+}
+
+impl<'a> Attempt<'a> {
+    /// Starts an attempt at sending a request to `module`, unless its circuit is open, letting
+    /// the interceptors modify its headers.
+    pub(crate) fn start(
+        breaker: Option<&'a CircuitBreaker>,
+        interceptors: &'a [Arc<dyn Interceptor>],
+        module: &str,
+        route: &'a str,
+        op: Option<&'a str>,
+        payload: &[u8],
+        headers: &mut Vec<(String, String)>,
+    ) -> Option<Self> {
+        let permit = match breaker {
+            None => None,
+            Some(breaker) => Some(breaker.acquire(module)?),
+        };
+
+        let mut sending = Sending {
+            route,
+            op,
+            payload,
+            headers,
+        };
+
+        for interceptor in interceptors.iter() {
+            interceptor.before_send(&mut sending);
+        }
+
+        Some(Attempt {
+            route,
+            op,
+            interceptors,
+            permit,
+            start: time::now_millis(),
+        })
+    }
+
+    /// Ends the attempt with the reply it got, or the error preventing one. Attempts interrupted
+    /// before either are dropped instead, leaving out the breaker and the interceptors.
+    pub(crate) fn end(self, reply: Result<Head, &str>) {
+        let received = Received {
+            route: self.route,
+            op: self.op,
+            status: reply.as_ref().ok().map(|head| head.status),
+            headers: reply.as_ref().map(|head| head.headers).unwrap_or(&[]),
+            error: reply.as_ref().err().copied(),
+            elapsed: Duration::from_millis(time::now_millis().saturating_sub(self.start)),
+        };
+
+        for interceptor in self.interceptors.iter().rev() {
+            interceptor.after_receive(&received);
+        }
+
+        if let Some(permit) = self.permit {
+            permit.record_reply(reply.ok());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn replies_are_read_in_the_format_they_declare() {
+        let headers = [header("Content-Type", "application/cbor")];
+        assert_eq!(Head::new(200, &headers).format(Format::Json), Format::Cbor);
+    }
+
+    #[test]
+    fn replies_in_unknown_formats_fall_back_to_the_default() {
+        let unknown = [header("content-type", "text/plain")];
+        assert_eq!(
+            Head::new(500, &unknown).format(Format::MessagePack),
+            Format::MessagePack
+        );
+        assert_eq!(Head::new(500, &[]).format(Format::Bincode), Format::Bincode);
+    }
+
+    #[test]
+    fn requests_accept_their_own_format_except_streams() {
+        let execute = headers(Format::Cbor, "execute", Some("greet"), None, &[]);
+        assert!(execute.contains(&header("content-type", "application/cbor")));
+        assert!(execute.contains(&header("accept", "application/cbor")));
+        assert!(execute.contains(&header(OP_HEADER, "greet")));
+
+        let stream = headers(Format::Cbor, "stream", Some("countdown"), None, &[]);
+        assert!(stream.contains(&header("content-type", "application/cbor")));
+        assert!(stream.contains(&header("accept", NDJSON)));
+    }
+
+    #[test]
+    fn deadlines_set_by_the_dispatcher_are_kept() {
+        let extra = [header(DEADLINE_HEADER, "1")];
+        let headers = headers(Format::Json, "batch", None, Some(2), &extra);
+
+        let deadlines: Vec<_> = headers
+            .iter()
+            .filter(|(name, _)| name == DEADLINE_HEADER)
+            .collect();
+        assert_eq!(deadlines, [&header(DEADLINE_HEADER, "1")]);
+    }
+}
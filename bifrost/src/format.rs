@@ -0,0 +1,149 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Serialization format used for op payloads, outputs and errors.
+///
+/// Formats are negotiated through the `Content-Type` (for op payloads) and `Accept` (for outputs
+/// and errors) headers, with JSON as the default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+    /// Not self-describing, so unsuitable for types that rely on `deserialize_any`, such as
+    /// `serde_json::Value` or BSON documents.
+    Bincode,
+}
+
+impl Format {
+    pub const ALL: [Format; 4] = [
+        Format::Json,
+        Format::MessagePack,
+        Format::Cbor,
+        Format::Bincode,
+    ];
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::MessagePack => "application/msgpack",
+            Format::Cbor => "application/cbor",
+            Format::Bincode => "application/x-bincode",
+        }
+    }
+
+    /// Resolves a content type, ignoring any parameters such as `charset`.
+    pub fn from_content_type(content_type: &str) -> Option<Self> {
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+
+        Self::ALL
+            .into_iter()
+            .find(|f| f.content_type().eq_ignore_ascii_case(media_type))
+    }
+
+    pub fn serialize<T>(&self, value: &T) -> Result<Vec<u8>, String>
+    where
+        T: Serialize + ?Sized,
+    {
+        match self {
+            Format::Json => serde_json::to_vec(value).map_err(|e| e.to_string()),
+            Format::MessagePack => rmp_serde::to_vec_named(value).map_err(|e| e.to_string()),
+            Format::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::ser::into_writer(value, &mut bytes).map_err(|e| e.to_string())?;
+                Ok(bytes)
+            }
+            Format::Bincode => bincode::serialize(value).map_err(|e| e.to_string()),
+        }
+    }
+
+    pub fn deserialize<T>(&self, bytes: &[u8]) -> Result<T, String>
+    where
+        T: DeserializeOwned,
+    {
+        match self {
+            Format::Json => serde_json::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::MessagePack => rmp_serde::from_slice(bytes).map_err(|e| e.to_string()),
+            Format::Cbor => ciborium::de::from_reader(bytes).map_err(|e| e.to_string()),
+            Format::Bincode => bincode::deserialize(bytes).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+    struct Payload {
+        name: String,
+        counts: BTreeMap<String, u32>,
+        tag: Option<char>,
+    }
+
+    #[test]
+    fn content_types_resolve_to_their_format() {
+        for format in Format::ALL {
+            assert_eq!(
+                Format::from_content_type(format.content_type()),
+                Some(format)
+            );
+        }
+    }
+
+    #[test]
+    fn content_type_parameters_and_case_are_ignored() {
+        assert_eq!(
+            Format::from_content_type("application/json; charset=utf-8"),
+            Some(Format::Json)
+        );
+        assert_eq!(
+            Format::from_content_type(" Application/MsgPack "),
+            Some(Format::MessagePack)
+        );
+    }
+
+    #[test]
+    fn unknown_content_types_are_not_resolved() {
+        for content_type in ["text/plain", "application/xml", "", ";"] {
+            assert_eq!(Format::from_content_type(content_type), None);
+        }
+    }
+
+    #[test]
+    fn values_round_trip_in_every_format() {
+        let payload = Payload {
+            name: String::from("bifrost"),
+            counts: BTreeMap::from([(String::from("a"), 1), (String::from("b"), 2)]),
+            tag: Some('x'),
+        };
+
+        for format in Format::ALL {
+            let bytes = format.serialize(&payload).unwrap();
+            assert_eq!(format.deserialize::<Payload>(&bytes), Ok(payload.clone()));
+        }
+    }
+
+    #[test]
+    fn mismatched_formats_are_reported() {
+        let bytes = Format::Cbor.serialize(&String::from("bifrost")).unwrap();
+        assert!(Format::Json.deserialize::<String>(&bytes).is_err());
+    }
+
+    #[test]
+    fn bincode_cannot_deserialize_self_describing_types() {
+        let bytes = Format::Bincode
+            .serialize(&serde_json::json!({ "a": 1 }))
+            .unwrap();
+
+        assert!(Format::Bincode
+            .deserialize::<serde_json::Value>(&bytes)
+            .is_err());
+        assert!(Format::Json
+            .deserialize::<serde_json::Value>(br#"{"a":1}"#)
+            .is_ok());
+    }
+}
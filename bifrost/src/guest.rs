@@ -0,0 +1,452 @@
+use crate::context::{self, Context, CONTEXT_VAR};
+use crate::format::Format;
+use crate::op::{Emitter, Op, StreamingOp};
+use crate::protocol::{
+    self, Call, Frame, Stage, BATCH_LABEL, PIPELINE_LABEL, RESERVED_PREFIX, SCHEMAS_LABEL,
+};
+use schemars::{schema_for, JsonSchema};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::io::{Read, Write};
+
+pub use crate::protocol::{exec, Handler, Outcome};
+
+/// Exit code signalling that the op returned an error, which is written to stderr, or for
+/// streaming ops as the last frame on stdout.
+pub const EXIT_OP_ERROR: i32 = 1;
+
+/// Exit code signalling that no op matches the requested label.
+pub const EXIT_UNKNOWN_OP: i32 = 2;
+
+/// Exit code signalling that the op payload could not be deserialized.
+pub const EXIT_INPUT_DECODE_ERROR: i32 = 3;
+
+/// Exit code signalling that the op output (or error) could not be serialized.
+pub const EXIT_OUTPUT_ENCODE_ERROR: i32 = 4;
+
+/// Exit code signalling that the op panicked, with the panic message written to stderr.
+pub const EXIT_PANIC: i32 = 5;
+
+/// Executes a streaming op serialized in the given format, writing its items to stdout as they
+/// are emitted and exiting on failure.
+pub type StreamHandler = fn(&[u8], Format);
+
+/// Describes the types of an op as JSON schemas, along with its id.
+pub type Describer = fn() -> (&'static str, Value);
+
+/// Runs the op named by the first argument on the payload read from stdin, reporting the result
+/// via stdout, stderr and the process exit code.
+///
+/// Under `BATCH_LABEL`, the payload is a list of calls instead, each executed in turn and their
+/// outcomes written to stdout together. A panic still aborts the whole batch.
+///
+/// Under `PIPELINE_LABEL`, the payload is a list of stages, and the output that of the last one.
+/// The first failed step is written to stderr as if it were an op error.
+///
+/// Under `SCHEMAS_LABEL`, the schemas of all ops are written to stdout as JSON, whatever the
+/// formats.
+///
+/// Streaming ops write one JSON `Frame` per line to stdout, regardless of the result format.
+///
+/// The second and third arguments are the content types of the payload and of the result,
+/// defaulting to JSON and to the payload format respectively.
+pub fn main(
+    ops: &[(&'static str, Handler)],
+    streams: &[(&'static str, StreamHandler)],
+    describers: &[Describer],
+) {
+    std::panic::set_hook(Box::new(|info| {
+        let payload = info.payload();
+
+        let message = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("Box<dyn Any>");
+
+        match info.location() {
+            Some(location) => exit(EXIT_PANIC, &format!("{} ({})", message, location)),
+            None => exit(EXIT_PANIC, message),
+        }
+    }));
+
+    let ctx = std::env::var(CONTEXT_VAR)
+        .ok()
+        .and_then(|json| serde_json::from_str::<Context>(&json).ok());
+    context::set(ctx);
+
+    let args: Vec<String> = std::env::args().collect();
+
+    let label = args.first().cloned().unwrap_or_default();
+    let input = format_arg(args.get(1), Format::default());
+    let output = format_arg(args.get(2), input);
+
+    let mut payload = Vec::new();
+    if let Err(e) = std::io::stdin().read_to_end(&mut payload) {
+        exit(
+            EXIT_INPUT_DECODE_ERROR,
+            &format!("unable to read payload: {}", e),
+        );
+    }
+
+    if label == SCHEMAS_LABEL {
+        let schemas: Map<String, Value> = describers
+            .iter()
+            .map(|describe| {
+                let (id, schemas) = describe();
+                (id.to_string(), schemas)
+            })
+            .collect();
+
+        match serde_json::to_vec(&schemas) {
+            Ok(bytes) => write_output(&bytes),
+            Err(e) => exit(EXIT_OUTPUT_ENCODE_ERROR, &e.to_string()),
+        }
+
+        return;
+    }
+
+    if label == BATCH_LABEL {
+        let calls: Vec<Call> = match input.deserialize(&payload) {
+            Ok(calls) => calls,
+            Err(e) => exit(EXIT_INPUT_DECODE_ERROR, &e),
+        };
+
+        let outcomes: Vec<Outcome> = calls
+            .iter()
+            .map(|call| match find(ops, &call.op) {
+                Some(handler) => handler(&call.payload, input, output),
+                None => Outcome::UnknownOp(format!("unknown op: {}", call.op)),
+            })
+            .collect();
+
+        match output.serialize(&outcomes) {
+            Ok(bytes) => write_output(&bytes),
+            Err(e) => exit(EXIT_OUTPUT_ENCODE_ERROR, &e),
+        }
+
+        return;
+    }
+
+    if label == PIPELINE_LABEL {
+        let stages: Vec<Stage> = match input.deserialize(&payload) {
+            Ok(stages) => stages,
+            Err(e) => exit(EXIT_INPUT_DECODE_ERROR, &e),
+        };
+
+        match protocol::run_pipeline(&stages, |op| find(ops, op), output) {
+            Ok(bytes) => write_output(&bytes),
+            Err(failure) => match output.serialize(&failure) {
+                Ok(bytes) => {
+                    let _ = std::io::stderr().write_all(&bytes);
+                    std::process::exit(EXIT_OP_ERROR)
+                }
+                Err(e) => exit(EXIT_OUTPUT_ENCODE_ERROR, &e),
+            },
+        }
+
+        return;
+    }
+
+    if let Some((_, handler)) = streams.iter().find(|(id, _)| *id == label.as_str()) {
+        handler(&payload, input);
+        return;
+    }
+
+    let handler = match find(ops, &label) {
+        Some(handler) => handler,
+        None => exit(EXIT_UNKNOWN_OP, &format!("unknown op: {}", label)),
+    };
+
+    match handler(&payload, input, output) {
+        Outcome::Success(output) => write_output(&output),
+        Outcome::OpError(e) => {
+            let _ = std::io::stderr().write_all(&e);
+            std::process::exit(EXIT_OP_ERROR)
+        }
+        Outcome::UnknownOp(e) => exit(EXIT_UNKNOWN_OP, &e),
+        Outcome::InputDecodeError(e) => exit(EXIT_INPUT_DECODE_ERROR, &e),
+        Outcome::OutputEncodeError(e) => exit(EXIT_OUTPUT_ENCODE_ERROR, &e),
+    }
+}
+
+pub fn exec_stream<T>(payload: &[u8], input: Format)
+where
+    T: StreamingOp + DeserializeOwned,
+    T::Item: Serialize,
+    T::Error: Serialize,
+{
+    let op: T = match input.deserialize(payload) {
+        Ok(op) => op,
+        Err(e) => exit(EXIT_INPUT_DECODE_ERROR, &e),
+    };
+
+    let mut emit = |item| write_frame(&Frame::<T::Item, T::Error>::Item(item));
+
+    if let Err(e) = op.execute(&mut Emitter::new(&mut emit)) {
+        write_frame(&Frame::<T::Item, T::Error>::Error(e));
+        std::process::exit(EXIT_OP_ERROR)
+    }
+}
+
+/// Schemas of the input, output and error of an op.
+pub fn describe<T>() -> (&'static str, Value)
+where
+    T: Op + JsonSchema,
+    T::Output: JsonSchema,
+    T::Error: JsonSchema,
+{
+    let schemas = json!({
+        "input": schema_for!(T),
+        "output": schema_for!(<T as Op>::Output),
+        "error": schema_for!(<T as Op>::Error),
+    });
+
+    (<T as Op>::id(), schemas)
+}
+
+/// Schemas of the input, items and error of a streaming op.
+pub fn describe_stream<T>() -> (&'static str, Value)
+where
+    T: StreamingOp + JsonSchema,
+    T::Item: JsonSchema,
+    T::Error: JsonSchema,
+{
+    let schemas = json!({
+        "input": schema_for!(T),
+        "item": schema_for!(<T as StreamingOp>::Item),
+        "error": schema_for!(<T as StreamingOp>::Error),
+    });
+
+    (<T as StreamingOp>::id(), schemas)
+}
+
+fn write_frame<T>(frame: &T)
+where
+    T: Serialize,
+{
+    let mut line = match serde_json::to_vec(frame) {
+        Ok(line) => line,
+        Err(e) => exit(EXIT_OUTPUT_ENCODE_ERROR, &e.to_string()),
+    };
+    line.push(b'\n');
+
+    write_output(&line);
+}
+
+/// Fails compilation if two of `ids` are equal, or if one of them starts with the prefix reserved
+/// for batches, pipelines and schemas, as the guest would otherwise execute the first op matching
+/// a label.
+///
+/// ```
+/// # use bifrost::op::{Never, Op};
+/// # macro_rules! ops {
+/// #     ( $( $name:ident = $id:literal ),* ) => { $(
+/// #         #[derive(serde::Deserialize, bifrost::__private::schemars::JsonSchema)]
+/// #         #[schemars(crate = "bifrost::__private::schemars")]
+/// #         struct $name;
+/// #         impl Op for $name {
+/// #             type Output = ();
+/// #             type Error = Never;
+/// #             const ID: &'static str = $id;
+/// #             fn execute(self) -> Result<(), Never> { Ok(()) }
+/// #         }
+/// #     )* };
+/// # }
+/// ops!(Greet = "greet", AddOne = "add_one");
+/// bifrost::entrypoint!(Greet, AddOne);
+/// ```
+///
+/// ```compile_fail
+/// # use bifrost::op::{Never, Op};
+/// # macro_rules! ops {
+/// #     ( $( $name:ident = $id:literal ),* ) => { $(
+/// #         #[derive(serde::Deserialize, bifrost::__private::schemars::JsonSchema)]
+/// #         #[schemars(crate = "bifrost::__private::schemars")]
+/// #         struct $name;
+/// #         impl Op for $name {
+/// #             type Output = ();
+/// #             type Error = Never;
+/// #             const ID: &'static str = $id;
+/// #             fn execute(self) -> Result<(), Never> { Ok(()) }
+/// #         }
+/// #     )* };
+/// # }
+/// ops!(Greet = "greet", Hello = "greet");
+/// bifrost::entrypoint!(Greet, Hello);
+/// ```
+///
+/// ```compile_fail
+/// # use bifrost::op::{Never, Op};
+/// # macro_rules! ops {
+/// #     ( $( $name:ident = $id:literal ),* ) => { $(
+/// #         #[derive(serde::Deserialize, bifrost::__private::schemars::JsonSchema)]
+/// #         #[schemars(crate = "bifrost::__private::schemars")]
+/// #         struct $name;
+/// #         impl Op for $name {
+/// #             type Output = ();
+/// #             type Error = Never;
+/// #             const ID: &'static str = $id;
+/// #             fn execute(self) -> Result<(), Never> { Ok(()) }
+/// #         }
+/// #     )* };
+/// # }
+/// ops!(Greet = "greet", Batch = "__bifrost_batch");
+/// bifrost::entrypoint!(Greet, Batch);
+/// ```
+#[doc(hidden)]
+pub const fn check_ids(ids: &[&str]) {
+    let mut i = 0;
+
+    while i < ids.len() {
+        if starts_with(ids[i], RESERVED_PREFIX) {
+            reserved_op_id(ids[i]);
+        }
+
+        let mut j = i + 1;
+
+        while j < ids.len() {
+            if eq(ids[i], ids[j]) {
+                duplicate_op_id(ids[i]);
+            }
+
+            j += 1;
+        }
+
+        i += 1;
+    }
+}
+
+// Named after the error they report, which the compiler points to when evaluating `check_ids`.
+
+const fn duplicate_op_id(id: &str) -> ! {
+    reject(["duplicate bifrost op id: ", id, ""])
+}
+
+const fn reserved_op_id(id: &str) -> ! {
+    reject([
+        "op id ",
+        id,
+        " is reserved (labels starting with `__bifrost_`)",
+    ])
+}
+
+/// Longest message reported by `reject`, past which it is truncated.
+const REJECTION_LEN: usize = 512;
+
+/// Fails compilation with a message made of `parts`, as const panics cannot format one.
+const fn reject(parts: [&str; 3]) -> ! {
+    let mut message = [0; REJECTION_LEN];
+    let mut len = 0;
+    let mut part = 0;
+
+    while part < parts.len() {
+        let bytes = parts[part].as_bytes();
+        let mut i = 0;
+
+        while i < bytes.len() && len < REJECTION_LEN {
+            message[len] = bytes[i];
+            len += 1;
+            i += 1;
+        }
+
+        part += 1;
+    }
+
+    match std::str::from_utf8(message.split_at(len).0) {
+        Ok(message) => panic!("{}", message),
+        // Truncated within a character.
+        Err(_) => panic!("{}", parts[1]),
+    }
+}
+
+const fn starts_with(s: &str, prefix: &str) -> bool {
+    let (s, prefix) = (s.as_bytes(), prefix.as_bytes());
+
+    if s.len() < prefix.len() {
+        return false;
+    }
+
+    let mut i = 0;
+
+    while i < prefix.len() {
+        if s[i] != prefix[i] {
+            return false;
+        }
+
+        i += 1;
+    }
+
+    true
+}
+
+const fn eq(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut i = 0;
+
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+
+        i += 1;
+    }
+
+    true
+}
+
+/// Copies a manifest entry into an array, which unlike a string slice can be placed in a custom
+/// section.
+#[doc(hidden)]
+pub const fn manifest_entry<const N: usize>(entry: &str) -> [u8; N] {
+    let bytes = entry.as_bytes();
+    let mut out = [0; N];
+    let mut i = 0;
+
+    while i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+
+    out
+}
+
+fn find(ops: &[(&'static str, Handler)], label: &str) -> Option<Handler> {
+    ops.iter()
+        .find(|(id, _)| *id == label)
+        .map(|(_, handler)| *handler)
+}
+
+fn write_output(output: &[u8]) {
+    let mut stdout = std::io::stdout();
+    if let Err(e) = stdout.write_all(output).and_then(|_| stdout.flush()) {
+        exit(
+            EXIT_OUTPUT_ENCODE_ERROR,
+            &format!("unable to write output: {}", e),
+        );
+    }
+}
+
+fn format_arg(arg: Option<&String>, default: Format) -> Format {
+    match arg {
+        None => default,
+        Some(content_type) => match Format::from_content_type(content_type) {
+            Some(format) => format,
+            None => exit(
+                EXIT_INPUT_DECODE_ERROR,
+                &format!("unsupported format: {}", content_type),
+            ),
+        },
+    }
+}
+
+fn exit(code: i32, message: &str) -> ! {
+    eprint!("{}", message);
+    std::process::exit(code)
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+/// Observes and amends the requests of a dispatcher, such as to attach authentication or tracing
+/// headers, or to log ops and their latency. See `Dispatcher::with_interceptor`.
+///
+/// Ops executed locally in debug mode go through interceptors as well: headers added before
+/// sending are exposed to ops through their context, and the reported status is the one heimdall
+/// would have answered with.
+pub trait Interceptor: Send + Sync {
+    /// Called before each request is sent, including retries.
+    fn before_send(&self, _request: &mut Sending) {}
+
+    /// Called once the reply to a request is received, or once it failed.
+    fn after_receive(&self, _response: &Received) {}
+}
+
+/// A request about to be sent.
+pub struct Sending<'a> {
+    /// Route of the request, such as `execute` or `batch`.
+    pub route: &'a str,
+    /// Id of the op, for requests carrying a single op.
+    pub op: Option<&'a str>,
+    /// Serialized body of the request.
+    pub payload: &'a [u8],
+    pub headers: &'a mut Vec<(String, String)>,
+}
+
+/// The outcome of a request.
+pub struct Received<'a> {
+    pub route: &'a str,
+    pub op: Option<&'a str>,
+    /// Status of the reply, unless none was received.
+    pub status: Option<u16>,
+    /// Headers of the reply.
+    pub headers: &'a [(String, String)],
+    /// Error preventing a reply from being received, such as a network error.
+    pub error: Option<&'a str>,
+    /// Time from sending the request to receiving the headers of its reply.
+    pub elapsed: Duration,
+}
@@ -1,38 +1,130 @@
 #[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+pub mod balancer;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+pub mod batch;
+#[cfg(feature = "local-native")]
+pub mod blocking;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+pub mod breaker;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+pub mod cache;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+pub mod call;
+#[cfg(any(feature = "local-browser", feature = "local-native"))]
+mod coalesce;
+pub mod context;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
 pub mod dispatcher;
+#[cfg(any(feature = "local-browser", feature = "local-native"))]
+mod exchange;
+pub mod format;
+#[cfg(feature = "remote")]
+pub mod guest;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+pub mod interceptor;
+#[cfg(all(
+    feature = "mock",
+    any(feature = "local-browser", feature = "local-native")
+))]
+pub mod mock;
+#[cfg(feature = "local-browser")]
+pub mod offline;
 pub mod op;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+pub mod pipeline;
+pub mod protocol;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+pub mod retry;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+pub mod stream;
+#[cfg(any(feature = "local-browser", feature = "local-native", feature = "debug"))]
+mod time;
+#[cfg(any(feature = "local-browser", feature = "local-native"))]
+pub mod transport;
+#[cfg(feature = "local-wasm")]
+pub mod wasm;
 
-#[macro_export]
-macro_rules! entrypoint {
-    ( $( $typ:ty ),* ) => {
-        fn main() {
-            use bifrost::op::Op;
-            use serde::Serialize;
-            use serde::de::DeserializeOwned;
+pub use bifrost_macros::op;
+#[cfg(any(feature = "remote", feature = "debug"))]
+pub use context::context;
+pub use context::Context;
 
-            let args : Vec<String> = std::env::args().collect();
+#[doc(hidden)]
+pub mod __private {
+    pub use schemars;
+    pub use serde;
+}
 
-            let label = &args[0];
-            let json = &args[1];
+/// Defines the `main` function of a guest executing the given ops, with streaming ops listed
+/// after `; stream`:
+///
+/// ```ignore
+/// bifrost::entrypoint!(Greet, AddOne; stream Countdown);
+/// ```
+///
+/// The manifest entries of the ops (see `Op::MANIFEST`) are embedded in the `bifrost_manifest`
+/// custom section of the module, from which heimdall reads the ops a module executes. The types of
+/// the ops, their outputs (or items) and errors must implement `schemars::JsonSchema`: the guest
+/// writes their schemas when run under `protocol::SCHEMAS_LABEL`, and heimdall adds them to the
+/// manifest.
+///
+/// Ops sharing an id (see `Op::ID`), or whose id starts with `__bifrost_`, the prefix reserved for
+/// batches, pipelines and schemas, fail compilation.
+#[macro_export]
+macro_rules! entrypoint {
+    ( $( $typ:ty ),* $( ; stream $( $stream:ty ),* )? ) => {
+        const _: () = $crate::guest::check_ids(&[
+            $( <$typ as $crate::op::Op>::ID, )*
+            $($( <$stream as $crate::op::StreamingOp>::ID, )*)?
+        ]);
 
-            $(
-                if label.as_str() == <$typ>::id() {
-                    __bifrost_dispatch::<$typ>(json);
-                }
-            )*
-        }
+        $(
+            $crate::__manifest_entry!(<$typ as $crate::op::Op>::MANIFEST);
+        )*
+        $($(
+            $crate::__manifest_entry!(<$stream as $crate::op::StreamingOp>::MANIFEST);
+        )*)?
 
-        fn __bifrost_dispatch<T>(json: &String) where T : Op + DeserializeOwned, T::Output : Serialize {
-            match __bifrost_exec::<T>(json) {
-                Some(result) => print!("{}", result),
-                None => ()
-            }
+        fn main() {
+            $crate::guest::main(
+                &[
+                    $(
+                        (
+                            <$typ as $crate::op::Op>::id(),
+                            $crate::guest::exec::<$typ> as $crate::guest::Handler,
+                        ),
+                    )*
+                ],
+                &[
+                    $($(
+                        (
+                            <$stream as $crate::op::StreamingOp>::id(),
+                            $crate::guest::exec_stream::<$stream> as $crate::guest::StreamHandler,
+                        ),
+                    )*)?
+                ],
+                &[
+                    $( $crate::guest::describe::<$typ> as $crate::guest::Describer, )*
+                    $($(
+                        $crate::guest::describe_stream::<$stream> as $crate::guest::Describer,
+                    )*)?
+                ],
+            );
         }
+    };
+}
 
-        fn __bifrost_exec<T>(json: &String) -> Option<String> where T : Op + DeserializeOwned, T::Output : Serialize {
-            let op : T = serde_json::from_str(json).map_err(|e| e.to_string()).ok()?;
-            let result = op.execute();
-            serde_json::to_string(&result).ok()
-        }
+/// Places a manifest entry in the `bifrost_manifest` custom section, where the entries of all
+/// ops are concatenated by the linker.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __manifest_entry {
+    ( $entry:expr ) => {
+        #[cfg(target_arch = "wasm32")]
+        const _: () = {
+            #[link_section = "bifrost_manifest"]
+            #[used]
+            static ENTRY: [u8; $entry.len()] = $crate::guest::manifest_entry($entry);
+        };
     };
 }
@@ -0,0 +1,683 @@
+use crate::dispatcher::{Dispatcher, Response, ERROR_HEADER, OP_ERROR, OP_HEADER};
+use crate::format::Format;
+use crate::op::{Op, StreamingOp};
+use crate::protocol::{self, Frame, Outcome, Stage, StageFailure, DEADLINE_EXCEEDED, NDJSON};
+use crate::transport::{BoxFuture, Reply, Request, Transport};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Stands in for heimdall in client unit tests, answering requests with programmed responses
+/// instead of executing ops.
+///
+/// The mock is a `Transport`, plugged into a real dispatcher with `dispatcher` or
+/// `Dispatcher::with_transport`, so that calls go through the formats, retries, timeouts, cache
+/// and interceptors of the dispatcher under test. Single ops, batches, pipelines and streams are
+/// answered as heimdall would answer them.
+///
+/// Responses are programmed per op id, either as canned responses or as closures of the op. Ops
+/// with no programmed response are answered with `Response::RequestError(404, ...)`, as heimdall
+/// answers unknown ops. Every call is recorded along with its payload, see `calls`.
+///
+/// Clones share their responses and calls, so that a clone can be handed to the dispatcher while
+/// the test keeps the other.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    inner: Arc<Inner>,
+}
+
+/// A `Dispatcher` answered by a `MockTransport`, for tests sending ops themselves.
+///
+/// It derefs to the dispatcher, so ops are sent with the same `send`, `send_with`, `send_batch`,
+/// `send_pipeline` and `send_stream` as in the client. Components holding a `Dispatcher` are given
+/// `into_dispatcher` or `MockTransport::dispatcher` instead.
+pub struct MockDispatcher {
+    dispatcher: Dispatcher,
+    mock: MockTransport,
+}
+
+#[derive(Default)]
+struct Inner {
+    handlers: Mutex<HashMap<&'static str, Handler>>,
+    streams: Mutex<HashMap<&'static str, Vec<u8>>>,
+    calls: Mutex<Vec<Call>>,
+}
+
+/// A call received by a `MockTransport`. Calls of a batch or pipeline are recorded one by one,
+/// with the input each step was executed with.
+#[derive(Clone, Debug)]
+pub struct Call {
+    /// Id of the module the call was sent to.
+    pub module: String,
+    /// Id of the op.
+    pub op: String,
+    /// Format the op is serialized in.
+    pub format: Format,
+    pub payload: Vec<u8>,
+}
+
+/// Answers a call serialized in the first format, in the second format.
+type Handler = Arc<dyn Fn(&[u8], Format, Format) -> Answer + Send + Sync>;
+
+/// How a programmed op answers a call.
+#[derive(Clone)]
+enum Answer {
+    /// The op was executed, or could not be, as reported by a guest.
+    Executed(Outcome),
+    /// The request fails as a whole.
+    Failed(Failure),
+}
+
+#[derive(Clone)]
+enum Failure {
+    Network(String),
+    Status(u16, String),
+    DeadlineExceeded,
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a dispatcher sending ops through the mock, to the module `module_id` unless they
+    /// declare their own.
+    pub fn dispatcher(&self, module_id: &str) -> Dispatcher {
+        Dispatcher::with_transport(self.clone(), module_id)
+    }
+
+    /// Answers every call of `T` with `response`.
+    ///
+    /// `Response::Timeout` is answered as an execution whose deadline passed, and
+    /// `Response::ParseError` with an output the dispatcher cannot decode. `Response::Cancelled`
+    /// and `Response::CircuitOpen`, which only dispatchers produce, are answered as network
+    /// errors.
+    pub fn with_response<T>(self, response: Response<T::Output, T::Error>) -> Self
+    where
+        T: Op,
+        T::Output: Serialize,
+        T::Error: Serialize,
+    {
+        let answers = Format::ALL.map(|format| (format, answer(&response, format)));
+
+        self.with(T::id(), move |_, _, output| {
+            // `Format::ALL` holds every format, so an answer is always found.
+            let (_, answer) = answers
+                .iter()
+                .find(|(format, _)| *format == output)
+                .unwrap_or(&answers[0]);
+
+            answer.clone()
+        })
+    }
+
+    /// Answers calls of `T` with the response `handler` returns for the op.
+    pub fn with_handler<T, F>(self, handler: F) -> Self
+    where
+        T: Op + DeserializeOwned,
+        T::Output: Serialize,
+        T::Error: Serialize,
+        F: Fn(T) -> Response<T::Output, T::Error> + Send + Sync + 'static,
+    {
+        self.with(T::id(), move |payload, input, output| {
+            match input.deserialize::<T>(payload) {
+                Ok(op) => answer(&handler(op), output),
+                Err(e) => Answer::Executed(Outcome::InputDecodeError(e)),
+            }
+        })
+    }
+
+    /// Fails every call of `T` with `Response::NetworkError`, as when heimdall cannot be reached.
+    pub fn with_network_error<T>(self, message: &str) -> Self
+    where
+        T: Op,
+    {
+        let failure = Failure::Network(message.to_string());
+        self.with(T::id(), move |_, _, _| Answer::Failed(failure.clone()))
+    }
+
+    /// Fails every call of `T` with `Response::RequestError`, as when heimdall rejects a request
+    /// or fails to execute it.
+    pub fn with_request_error<T>(self, status: u16, message: &str) -> Self
+    where
+        T: Op,
+    {
+        let failure = Failure::Status(status, message.to_string());
+        self.with(T::id(), move |_, _, _| Answer::Failed(failure.clone()))
+    }
+
+    /// Answers every call of the streaming op `T` with `items`, followed by `error` if there is
+    /// one.
+    pub fn with_items<T>(self, items: Vec<T::Item>, error: Option<T::Error>) -> Self
+    where
+        T: StreamingOp,
+        T::Item: Serialize,
+        T::Error: Serialize,
+    {
+        let mut frames: Vec<Frame<T::Item, T::Error>> =
+            items.into_iter().map(Frame::Item).collect();
+        frames.extend(error.map(Frame::Error));
+
+        let mut body = Vec::new();
+
+        for frame in frames.iter() {
+            match serde_json::to_vec(frame) {
+                Ok(line) => body.extend(line),
+                // As a guest would, the stream ends with the failure to encode an item.
+                Err(e) => {
+                    body.extend(failure_frame(500, "output_encode", &e.to_string()));
+                    break;
+                }
+            }
+
+            body.push(b'\n');
+        }
+
+        lock(&self.inner.streams).insert(T::id(), body);
+        self
+    }
+
+    fn with<F>(self, op: &'static str, handler: F) -> Self
+    where
+        F: Fn(&[u8], Format, Format) -> Answer + Send + Sync + 'static,
+    {
+        lock(&self.inner.handlers).insert(op, Arc::new(handler));
+        self
+    }
+
+    /// Calls received so far, in order.
+    pub fn calls(&self) -> Vec<Call> {
+        lock(&self.inner.calls).clone()
+    }
+
+    /// Calls of `T` received so far, in order, decoded back into ops.
+    pub fn calls_of<T>(&self) -> Vec<T>
+    where
+        T: Op + DeserializeOwned,
+    {
+        self.calls()
+            .into_iter()
+            .filter(|call| call.op == T::id())
+            .filter_map(|call| call.format.deserialize(&call.payload).ok())
+            .collect()
+    }
+
+    /// Records a call and answers it with the response programmed for its op.
+    fn call(
+        &self,
+        module: &str,
+        op: &str,
+        payload: &[u8],
+        input: Format,
+        output: Format,
+    ) -> Answer {
+        lock(&self.inner.calls).push(Call {
+            module: module.to_string(),
+            op: op.to_string(),
+            format: input,
+            payload: payload.to_vec(),
+        });
+
+        let handler = lock(&self.inner.handlers).get(op).cloned();
+
+        match handler {
+            Some(handler) => handler(payload, input, output),
+            None => Answer::Executed(Outcome::UnknownOp(unprogrammed(op))),
+        }
+    }
+
+    fn execute(
+        &self,
+        module: &str,
+        request: &Request,
+        input: Format,
+        output: Format,
+    ) -> Result<Reply, String> {
+        let op = header(request, OP_HEADER).unwrap_or_default();
+
+        match self.call(module, op, &request.body, input, output) {
+            Answer::Executed(outcome) => Ok(executed(outcome, output)),
+            Answer::Failed(failure) => failed(failure),
+        }
+    }
+
+    fn batch(
+        &self,
+        module: &str,
+        request: &Request,
+        input: Format,
+        output: Format,
+    ) -> Result<Reply, String> {
+        let calls: Vec<protocol::Call> = match input.deserialize(&request.body) {
+            Ok(calls) => calls,
+            Err(e) => return Ok(text(400, &e)),
+        };
+
+        let mut outcomes = Vec::with_capacity(calls.len());
+
+        for call in calls.iter() {
+            match self.call(module, &call.op, &call.payload, input, output) {
+                Answer::Executed(outcome) => outcomes.push(outcome),
+                Answer::Failed(failure) => return failed(failure),
+            }
+        }
+
+        Ok(executed(
+            output
+                .serialize(&outcomes)
+                .map_or_else(Outcome::OutputEncodeError, Outcome::Success),
+            output,
+        ))
+    }
+
+    /// Runs the stages of a pipeline as `protocol::run_pipeline` does, intermediate steps
+    /// exchanging JSON.
+    fn pipeline(
+        &self,
+        module: &str,
+        request: &Request,
+        input: Format,
+        output: Format,
+    ) -> Result<Reply, String> {
+        let stages: Vec<Stage> = match input.deserialize(&request.body) {
+            Ok(stages) => stages,
+            Err(e) => return Ok(text(400, &e)),
+        };
+
+        let outcome = match self.run_stages(module, &stages, output) {
+            Err(failure) => return failed(failure),
+            Ok(Ok(bytes)) => Outcome::Success(bytes),
+            Ok(Err(failure)) => output
+                .serialize(&failure)
+                .map_or_else(Outcome::OutputEncodeError, Outcome::OpError),
+        };
+
+        Ok(executed(outcome, output))
+    }
+
+    fn run_stages(
+        &self,
+        module: &str,
+        stages: &[Stage],
+        output: Format,
+    ) -> Result<Result<Vec<u8>, StageFailure>, Failure> {
+        let mut outputs: Vec<Value> = Vec::with_capacity(stages.len());
+
+        for (step, stage) in stages.iter().enumerate() {
+            let fail = |outcome| Ok(Err(StageFailure { step, outcome }));
+
+            let bound = match protocol::bind(stage, &outputs) {
+                Ok(bound) => bound,
+                Err(e) => return fail(Outcome::InputDecodeError(e)),
+            };
+
+            let last = step + 1 == stages.len();
+            let format = if last { output } else { Format::Json };
+
+            let outcome = match self.call(module, &stage.op, &bound, Format::Json, format) {
+                Answer::Executed(outcome) => outcome,
+                Answer::Failed(failure) => return Err(failure),
+            };
+
+            match outcome {
+                Outcome::Success(bytes) if last => return Ok(Ok(bytes)),
+                Outcome::Success(bytes) => match serde_json::from_slice(&bytes) {
+                    Ok(value) => outputs.push(value),
+                    Err(e) => return fail(Outcome::OutputEncodeError(e.to_string())),
+                },
+                outcome => return fail(outcome),
+            }
+        }
+
+        Ok(Err(StageFailure {
+            step: 0,
+            outcome: Outcome::InputDecodeError("empty pipeline".to_string()),
+        }))
+    }
+
+    fn stream(&self, module: &str, request: &Request, input: Format) -> Result<Reply, String> {
+        let op = header(request, OP_HEADER).unwrap_or_default();
+
+        lock(&self.inner.calls).push(Call {
+            module: module.to_string(),
+            op: op.to_string(),
+            format: input,
+            payload: request.body.clone(),
+        });
+
+        match lock(&self.inner.streams).get(op) {
+            Some(body) => Ok(reply(200, vec![("content-type", NDJSON)], body.clone())),
+            None => Ok(text(404, &unprogrammed(op))),
+        }
+    }
+}
+
+impl MockDispatcher {
+    /// Creates a dispatcher answered by `mock`, sending ops to the module `module_id` unless they
+    /// declare their own.
+    pub fn new(mock: MockTransport, module_id: &str) -> Self {
+        MockDispatcher {
+            dispatcher: mock.dispatcher(module_id),
+            mock,
+        }
+    }
+
+    /// Configures the dispatcher, such as with `Dispatcher::with_retry` or `with_format`.
+    pub fn with(self, configure: impl FnOnce(Dispatcher) -> Dispatcher) -> Self {
+        MockDispatcher {
+            dispatcher: configure(self.dispatcher),
+            mock: self.mock,
+        }
+    }
+
+    /// The mock answering the dispatcher, through which calls are inspected.
+    pub fn mock(&self) -> &MockTransport {
+        &self.mock
+    }
+
+    pub fn into_dispatcher(self) -> Dispatcher {
+        self.dispatcher
+    }
+}
+
+impl Deref for MockDispatcher {
+    type Target = Dispatcher;
+
+    fn deref(&self) -> &Dispatcher {
+        &self.dispatcher
+    }
+}
+
+impl From<MockDispatcher> for Dispatcher {
+    fn from(mock: MockDispatcher) -> Self {
+        mock.dispatcher
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, request: Request) -> BoxFuture<'_, Result<Reply, String>> {
+        let mut segments = request.path.rsplit('/');
+        let route = segments.next().unwrap_or_default();
+        let module = segments.next().unwrap_or_default();
+
+        let input = header(&request, "content-type")
+            .and_then(Format::from_content_type)
+            .unwrap_or_default();
+        let output = header(&request, "accept")
+            .and_then(Format::from_content_type)
+            .unwrap_or(input);
+
+        let result = match route {
+            "execute" => self.execute(module, &request, input, output),
+            "batch" => self.batch(module, &request, input, output),
+            "pipeline" => self.pipeline(module, &request, input, output),
+            "stream" => self.stream(module, &request, input),
+            _ => Ok(text(404, &format!("no route {}", request.path))),
+        };
+
+        Box::pin(futures::future::ready(result))
+    }
+}
+
+/// Answers a call with `response`, serialized in `format`.
+fn answer<T, E>(response: &Response<T, E>, format: Format) -> Answer
+where
+    T: Serialize,
+    E: Serialize,
+{
+    let encoded = |result: Result<Vec<u8>, String>, outcome: fn(Vec<u8>) -> Outcome| {
+        Answer::Executed(result.map_or_else(Outcome::OutputEncodeError, outcome))
+    };
+
+    match response {
+        Response::Success(v) => encoded(format.serialize(v), Outcome::Success),
+        Response::OpError(e) => encoded(format.serialize(e), Outcome::OpError),
+        Response::NetworkError(e) => Answer::Failed(Failure::Network(e.clone())),
+        Response::RequestError(status, e) => Answer::Failed(Failure::Status(*status, e.clone())),
+        Response::ParseError(_) => Answer::Executed(Outcome::Success(Vec::new())),
+        Response::Timeout => Answer::Failed(Failure::DeadlineExceeded),
+        Response::Cancelled => Answer::Failed(Failure::Network("cancelled".to_string())),
+        Response::CircuitOpen => Answer::Failed(Failure::Network("circuit open".to_string())),
+    }
+}
+
+/// Reply of heimdall to the execution of a single op, with the statuses of `runtime.rs`.
+fn executed(outcome: Outcome, format: Format) -> Reply {
+    let content_type = format.content_type();
+
+    match outcome {
+        Outcome::Success(bytes) => reply(200, vec![("content-type", content_type)], bytes),
+        Outcome::OpError(bytes) => reply(
+            422,
+            vec![(ERROR_HEADER, OP_ERROR), ("content-type", content_type)],
+            bytes,
+        ),
+        Outcome::UnknownOp(e) => text(404, &e),
+        Outcome::InputDecodeError(e) => text(400, &e),
+        Outcome::OutputEncodeError(e) => text(500, &e),
+    }
+}
+
+fn failed(failure: Failure) -> Result<Reply, String> {
+    match failure {
+        Failure::Network(e) => Err(e),
+        Failure::Status(status, e) => Ok(text(status, &e)),
+        Failure::DeadlineExceeded => Ok(reply(
+            504,
+            vec![(ERROR_HEADER, DEADLINE_EXCEEDED)],
+            b"Deadline exceeded".to_vec(),
+        )),
+    }
+}
+
+fn failure_frame(status: u16, kind: &str, message: &str) -> Vec<u8> {
+    let frame = Frame::<(), ()>::Failure {
+        status,
+        kind: kind.to_string(),
+        message: message.to_string(),
+    };
+
+    serde_json::to_vec(&frame).unwrap_or_default()
+}
+
+fn text(status: u16, message: &str) -> Reply {
+    reply(status, Vec::new(), message.as_bytes().to_vec())
+}
+
+fn reply(status: u16, headers: Vec<(&str, &str)>, body: Vec<u8>) -> Reply {
+    let body = futures::stream::once(async move { Ok(body) });
+
+    Reply {
+        status,
+        headers: headers
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+        #[cfg(not(target_arch = "wasm32"))]
+        body: body.boxed(),
+        #[cfg(target_arch = "wasm32")]
+        body: body.boxed_local(),
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn unprogrammed(op: &str) -> String {
+    format!("No response programmed for {}", op)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(e) => e.into_inner(),
+    }
+}
+
+#[cfg(all(test, feature = "local-native"))]
+mod tests {
+    use super::*;
+    use crate::call::CallOptions;
+    use crate::retry::RetryPolicy;
+    use serde::Deserialize;
+    use std::future::Future;
+    use std::time::Duration;
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Increment {
+        i: i32,
+    }
+
+    impl Op for Increment {
+        type Output = i32;
+        type Error = String;
+
+        const IDEMPOTENT: bool = true;
+
+        const ID: &'static str = "increment";
+    }
+
+    #[derive(Deserialize, Serialize)]
+    struct Reset;
+
+    impl Op for Reset {
+        type Output = ();
+        type Error = String;
+
+        const ID: &'static str = "reset";
+    }
+
+    #[derive(Serialize)]
+    struct Count;
+
+    impl StreamingOp for Count {
+        type Item = u32;
+        type Error = String;
+
+        const ID: &'static str = "count";
+    }
+
+    fn increments() -> MockTransport {
+        MockTransport::new().with_handler(|op: Increment| Response::Success(op.i + 1))
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn answers_ops_in_the_format_of_the_dispatcher() {
+        let mock = increments();
+        let dispatcher = mock.dispatcher("counter").with_format(Format::MessagePack);
+
+        let response = block_on(dispatcher.send(&Increment { i: 41 }));
+
+        assert!(matches!(response, Response::Success(42)));
+        assert_eq!(mock.calls_of::<Increment>(), vec![Increment { i: 41 }]);
+        assert_eq!(mock.calls()[0].module, "counter");
+        assert_eq!(mock.calls()[0].format, Format::MessagePack);
+    }
+
+    #[test]
+    fn answers_unprogrammed_ops_as_unknown() {
+        let dispatcher = increments().dispatcher("counter");
+
+        let response = block_on(dispatcher.send(&Reset));
+
+        assert!(matches!(response, Response::RequestError(404, _)));
+    }
+
+    #[test]
+    fn lets_the_dispatcher_retry_failures() {
+        let mock = MockTransport::new().with_request_error::<Increment>(503, "unavailable");
+        let policy = RetryPolicy::new(3).with_backoff(Duration::ZERO, Duration::ZERO);
+        let dispatcher = mock.dispatcher("counter").with_retry(policy);
+
+        let response = block_on(dispatcher.send(&Increment { i: 0 }));
+
+        assert!(matches!(response, Response::RequestError(503, e) if e == "unavailable"));
+        assert_eq!(mock.calls().len(), 3);
+    }
+
+    #[test]
+    fn answers_timeouts_as_exceeded_deadlines() {
+        let mock = MockTransport::new().with_response::<Increment>(Response::Timeout);
+
+        let response = block_on(mock.dispatcher("counter").send(&Increment { i: 0 }));
+
+        assert!(matches!(response, Response::Timeout));
+    }
+
+    #[test]
+    fn answers_each_op_of_a_batch() {
+        let dispatcher = increments().dispatcher("counter");
+        let mut batch = dispatcher.batch();
+        let sum = batch.add(&Increment { i: 1 });
+        let reset = batch.add(&Reset);
+
+        let results = block_on(dispatcher.send_batch(&batch));
+
+        assert!(matches!(results.get(sum), Response::Success(2)));
+        assert!(matches!(results.get(reset), Response::RequestError(404, _)));
+    }
+
+    #[test]
+    fn runs_pipelines_with_their_bindings() {
+        let mock = increments();
+        let dispatcher = mock.dispatcher("counter");
+        let pipeline = dispatcher.pipeline(&Increment { i: 1 });
+        let first = pipeline.step();
+        let pipeline = pipeline.then(&Increment { i: 0 }).bind("/i", first, "");
+
+        let response = block_on(dispatcher.send_pipeline(&pipeline));
+
+        assert!(matches!(response, Response::Success(3)));
+        assert_eq!(mock.calls_of::<Increment>()[1], Increment { i: 2 });
+    }
+
+    #[test]
+    fn streams_items_followed_by_their_error() {
+        let mock = MockTransport::new().with_items::<Count>(vec![1, 2], Some("done".to_string()));
+
+        let items: Vec<_> = block_on(async {
+            mock.dispatcher("counter")
+                .send_stream(&Count)
+                .await
+                .collect()
+                .await
+        });
+
+        assert!(matches!(
+            items.as_slice(),
+            [Response::Success(1), Response::Success(2), Response::OpError(e)] if e == "done"
+        ));
+    }
+
+    #[test]
+    fn mock_dispatchers_send_ops_as_dispatchers_do() {
+        let dispatcher =
+            MockDispatcher::new(increments(), "counter").with(|d| d.with_format(Format::Cbor));
+
+        let response = block_on(dispatcher.send(&Increment { i: 1 }));
+        let options = CallOptions::new().with_timeout(Duration::from_secs(1));
+        let sent_with = block_on(dispatcher.send_with(&Increment { i: 2 }, &options));
+
+        assert!(matches!(response, Response::Success(2)));
+        assert!(matches!(sent_with, Response::Success(3)));
+        assert_eq!(dispatcher.mock().calls()[0].format, Format::Cbor);
+        assert_eq!(dispatcher.mock().calls_of::<Increment>().len(), 2);
+    }
+}
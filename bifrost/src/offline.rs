@@ -0,0 +1,439 @@
+use crate::dispatcher::{Dispatcher, Response};
+use crate::format::Format;
+use crate::op::Op;
+use futures::channel::oneshot;
+use gloo::events::EventListener;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::rc::{Rc, Weak};
+use std::sync::Arc;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{IdbCursorWithValue, IdbDatabase, IdbObjectStore, IdbRequest, IdbTransactionMode};
+
+/// Object store holding queued ops, keyed on an auto-incremented number.
+const STORE: &str = "ops";
+
+/// Persists ops in IndexedDB before sending them, so that ops sent while offline are delivered in
+/// order once the network comes back, even after the page is reloaded.
+///
+/// Ops are replayed when the browser goes back online, and can be replayed explicitly with
+/// `replay`. Replaying stops at the first op failing with a network error, a timeout, an open
+/// circuit breaker or a `RequestError` with a 5xx status, which is retried on the next replay.
+/// Ops rejected by heimdall with a 4xx `RequestError` are passed to the conflict hook, which
+/// decides whether to discard them or to keep them at the head of the queue. Queued ops that
+/// cannot be read back are removed, completing with a `ParseError`.
+///
+/// Delivery is at least once: an op whose request timed out or failed after reaching heimdall may
+/// have been executed, and is executed again when replayed. Queued ops should therefore be
+/// idempotent, or able to detect that they were already applied.
+///
+/// Ops are stored in the format of the dispatcher, which must therefore not change across
+/// sessions using the same database.
+pub struct OfflineQueue {
+    inner: Rc<Inner>,
+}
+
+struct Inner {
+    dispatcher: Arc<Dispatcher>,
+    db: IdbDatabase,
+    /// Completions of ops queued in this session, by key.
+    waiting: RefCell<HashMap<u64, oneshot::Sender<Delivery>>>,
+    conflict: RefCell<Option<ConflictHook>>,
+    pending: Cell<usize>,
+    replaying: Cell<bool>,
+    online: RefCell<Option<EventListener>>,
+}
+
+/// A queued op, as stored in IndexedDB.
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    /// Module declared by the op, if any.
+    #[serde(default)]
+    module: Option<String>,
+    op: String,
+    #[serde(with = "serde_bytes")]
+    payload: Vec<u8>,
+}
+
+type ConflictHook = Box<dyn Fn(&Conflict) -> Resolution>;
+
+/// Outcome of a queued op, with its output and error still serialized in the given format.
+type Delivery = (Format, Response<Vec<u8>, Vec<u8>>);
+
+#[derive(Clone, Copy, Debug)]
+pub struct QueueStatus {
+    /// Number of ops waiting to be delivered.
+    pub pending: usize,
+    /// Whether the browser is online.
+    pub online: bool,
+    /// Whether ops are being replayed.
+    pub replaying: bool,
+}
+
+/// A queued op rejected by heimdall with a 4xx status when replayed.
+#[derive(Debug)]
+pub struct Conflict {
+    pub op: String,
+    /// The op, serialized in the format of the dispatcher.
+    pub payload: Vec<u8>,
+    pub status: u16,
+    pub message: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Removes the op from the queue, completing it with its `RequestError`.
+    Discard,
+    /// Keeps the op at the head of the queue, pausing replays until the next one.
+    Keep,
+}
+
+/// Completes once a queued op is delivered.
+pub struct Completion<T> {
+    receiver: oneshot::Receiver<Delivery>,
+    op: PhantomData<T>,
+}
+
+impl OfflineQueue {
+    /// Opens the queue stored in the IndexedDB database `name`, replaying ops left over from
+    /// previous sessions.
+    pub async fn open(name: &str, dispatcher: Arc<Dispatcher>) -> Result<Self, String> {
+        let db = open(name).await?;
+        let count = wait(
+            &store(&db, IdbTransactionMode::Readonly)?
+                .count()
+                .map_err(js_error)?,
+        )
+        .await?
+        .as_f64()
+        .unwrap_or(0.0) as usize;
+
+        let inner = Rc::new(Inner {
+            dispatcher,
+            db,
+            waiting: RefCell::new(HashMap::new()),
+            conflict: RefCell::new(None),
+            pending: Cell::new(count),
+            replaying: Cell::new(false),
+            online: RefCell::new(None),
+        });
+
+        if let Some(window) = web_sys::window() {
+            let weak = Rc::downgrade(&inner);
+            let listener = EventListener::new(&window, "online", move |_| spawn_replay(&weak));
+            *inner.online.borrow_mut() = Some(listener);
+        }
+
+        spawn_replay(&Rc::downgrade(&inner));
+
+        Ok(OfflineQueue { inner })
+    }
+
+    /// Sets the hook deciding what happens to ops rejected by heimdall when replayed. Without a
+    /// hook, they are discarded.
+    pub fn on_conflict<F>(&self, hook: F)
+    where
+        F: Fn(&Conflict) -> Resolution + 'static,
+    {
+        *self.inner.conflict.borrow_mut() = Some(Box::new(hook));
+    }
+
+    /// Queues an op, to be sent as soon as the ops queued before it are delivered and the browser
+    /// is online.
+    pub async fn send<T>(&self, op: &T) -> Completion<T>
+    where
+        T: Op + Serialize,
+    {
+        let (sender, receiver) = oneshot::channel();
+        let completion = Completion {
+            receiver,
+            op: PhantomData,
+        };
+
+        let format = self.inner.dispatcher.format();
+
+        let entry = match format.serialize(op) {
+            Ok(payload) => Entry {
+                module: T::MODULE.map(str::to_string),
+                op: T::id().to_string(),
+                payload,
+            },
+            Err(e) => {
+                let _ = sender.send((format, Response::ParseError(e)));
+                return completion;
+            }
+        };
+
+        match add(&self.inner.db, &entry).await {
+            Ok(key) => {
+                self.inner.waiting.borrow_mut().insert(key, sender);
+                self.inner.pending.set(self.inner.pending.get() + 1);
+                spawn_replay(&Rc::downgrade(&self.inner));
+            }
+            // Without storage, the op is sent right away rather than lost.
+            Err(_) => {
+                let dispatcher = self.inner.dispatcher.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let delivery = dispatcher
+                        .send_serialized(entry.module.as_deref(), &entry.op, entry.payload)
+                        .await;
+                    let _ = sender.send(delivery);
+                });
+            }
+        }
+
+        completion
+    }
+
+    /// Replays queued ops, such as after a conflict was resolved.
+    pub async fn replay(&self) {
+        replay(self.inner.clone()).await
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        QueueStatus {
+            pending: self.inner.pending.get(),
+            online: is_online(),
+            replaying: self.inner.replaying.get(),
+        }
+    }
+}
+
+impl<T> Completion<T>
+where
+    T: Op,
+    T::Output: DeserializeOwned,
+    T::Error: DeserializeOwned,
+{
+    /// Waits for the op to be delivered, which can take until the network comes back. Resolves to
+    /// `Response::Cancelled` if the queue is dropped before then.
+    pub async fn response(self) -> Response<T::Output, T::Error> {
+        let (format, response) = match self.receiver.await {
+            Ok(delivery) => delivery,
+            Err(_) => return Response::Cancelled,
+        };
+
+        match response {
+            Response::Success(bytes) => match format.deserialize(&bytes) {
+                Ok(v) => Response::Success(v),
+                Err(e) => Response::ParseError(e),
+            },
+            Response::OpError(bytes) => match format.deserialize(&bytes) {
+                Ok(e) => Response::OpError(e),
+                Err(e) => Response::ParseError(e),
+            },
+            Response::NetworkError(e) => Response::NetworkError(e),
+            Response::RequestError(status, e) => Response::RequestError(status, e),
+            Response::ParseError(e) => Response::ParseError(e),
+            Response::Timeout => Response::Timeout,
+            Response::Cancelled => Response::Cancelled,
+            Response::CircuitOpen => Response::CircuitOpen,
+        }
+    }
+}
+
+fn spawn_replay(inner: &Weak<Inner>) {
+    if let Some(inner) = inner.upgrade() {
+        wasm_bindgen_futures::spawn_local(replay(inner));
+    }
+}
+
+/// Sends queued ops in order, until the queue is empty or an op cannot be delivered.
+async fn replay(inner: Rc<Inner>) {
+    while !inner.replaying.replace(true) {
+        let drained = deliver(&inner).await;
+        inner.replaying.set(false);
+
+        // Ops queued while the queue was read for the last time found this replay running and
+        // left their delivery to it, so the queue is read again once it no longer runs.
+        if !drained || !matches!(first(&inner.db).await, Ok(Some(_))) {
+            break;
+        }
+    }
+}
+
+/// Sends queued ops in order, returning whether the queue was emptied.
+async fn deliver(inner: &Inner) -> bool {
+    while is_online() {
+        let (key, entry) = match first(&inner.db).await {
+            Ok(Some((key, Ok(entry)))) => (key, entry),
+            // Entries that cannot be read would otherwise block the queue forever.
+            Ok(Some((key, Err(e)))) => {
+                if delete(&inner.db, key).await.is_err() {
+                    return false;
+                }
+
+                complete(
+                    inner,
+                    key,
+                    (inner.dispatcher.format(), Response::ParseError(e)),
+                );
+                continue;
+            }
+            Ok(None) => return true,
+            Err(_) => return false,
+        };
+
+        let (format, response) = inner
+            .dispatcher
+            .send_serialized(entry.module.as_deref(), &entry.op, entry.payload.clone())
+            .await;
+
+        let delivered = match &response {
+            Response::NetworkError(_) | Response::Timeout | Response::CircuitOpen => false,
+            // Failures of heimdall or of the module rather than rejections of the op.
+            Response::RequestError(status, _) if *status >= 500 => false,
+            Response::RequestError(status, message) => {
+                let conflict = Conflict {
+                    op: entry.op,
+                    payload: entry.payload,
+                    status: *status,
+                    message: message.clone(),
+                };
+
+                match &*inner.conflict.borrow() {
+                    Some(hook) => hook(&conflict) == Resolution::Discard,
+                    None => true,
+                }
+            }
+            _ => true,
+        };
+
+        if !delivered || delete(&inner.db, key).await.is_err() {
+            return false;
+        }
+
+        complete(inner, key, (format, response));
+    }
+
+    false
+}
+
+/// Completes an op removed from the queue.
+fn complete(inner: &Inner, key: u64, delivery: Delivery) {
+    inner.pending.set(inner.pending.get().saturating_sub(1));
+
+    if let Some(sender) = inner.waiting.borrow_mut().remove(&key) {
+        let _ = sender.send(delivery);
+    }
+}
+
+fn is_online() -> bool {
+    web_sys::window()
+        .map(|window| window.navigator().on_line())
+        .unwrap_or(true)
+}
+
+async fn open(name: &str) -> Result<IdbDatabase, String> {
+    let factory = web_sys::window()
+        .ok_or("no window")?
+        .indexed_db()
+        .map_err(js_error)?
+        .ok_or("IndexedDB is not available")?;
+
+    let request = factory.open_with_u32(name, 1).map_err(js_error)?;
+
+    let upgrade = {
+        let opening = request.clone();
+        EventListener::once(&request, "upgradeneeded", move |_| {
+            if let Ok(db) = opening.result() {
+                let parameters = web_sys::IdbObjectStoreParameters::new();
+                parameters.set_auto_increment(true);
+
+                let db: IdbDatabase = db.unchecked_into();
+                let _ = db.create_object_store_with_optional_parameters(STORE, &parameters);
+            }
+        })
+    };
+
+    let db = wait(&request).await;
+    drop(upgrade);
+
+    Ok(db?.unchecked_into())
+}
+
+fn store(db: &IdbDatabase, mode: IdbTransactionMode) -> Result<IdbObjectStore, String> {
+    db.transaction_with_str_and_mode(STORE, mode)
+        .and_then(|transaction| transaction.object_store(STORE))
+        .map_err(js_error)
+}
+
+async fn add(db: &IdbDatabase, entry: &Entry) -> Result<u64, String> {
+    let value = serde_json::to_string(entry).map_err(|e| e.to_string())?;
+    let request = store(db, IdbTransactionMode::Readwrite)?
+        .add(&JsValue::from_str(&value))
+        .map_err(js_error)?;
+
+    key(&wait(&request).await?)
+}
+
+async fn delete(db: &IdbDatabase, key: u64) -> Result<(), String> {
+    let request = store(db, IdbTransactionMode::Readwrite)?
+        .delete(&JsValue::from_f64(key as f64))
+        .map_err(js_error)?;
+
+    wait(&request).await.map(|_| ())
+}
+
+/// The oldest queued op, with its key, or why it cannot be read.
+async fn first(db: &IdbDatabase) -> Result<Option<(u64, Result<Entry, String>)>, String> {
+    let request = store(db, IdbTransactionMode::Readonly)?
+        .open_cursor()
+        .map_err(js_error)?;
+
+    let cursor = wait(&request).await?;
+    if cursor.is_null() || cursor.is_undefined() {
+        return Ok(None);
+    }
+
+    let cursor: IdbCursorWithValue = cursor.unchecked_into();
+    let key = key(&cursor.key().map_err(js_error)?)?;
+    let entry = cursor
+        .value()
+        .map_err(js_error)?
+        .as_string()
+        .ok_or_else(|| "queued op is not a string".to_string())
+        .and_then(|value| {
+            serde_json::from_str(&value).map_err(|e| format!("invalid queued op: {}", e))
+        });
+
+    Ok(Some((key, entry)))
+}
+
+fn key(value: &JsValue) -> Result<u64, String> {
+    value
+        .as_f64()
+        .map(|key| key as u64)
+        .ok_or_else(|| "queued op key is not a number".to_string())
+}
+
+/// Waits for a request to complete, returning its result.
+async fn wait(request: &IdbRequest) -> Result<JsValue, String> {
+    let (sender, receiver) = oneshot::channel();
+    let sender = Rc::new(RefCell::new(Some(sender)));
+
+    let listeners = [("success", true), ("error", false)].map(|(event, success)| {
+        let sender = sender.clone();
+        EventListener::once(request, event, move |_| {
+            if let Some(sender) = sender.borrow_mut().take() {
+                let _ = sender.send(success);
+            }
+        })
+    });
+
+    let success = receiver.await.unwrap_or(false);
+    drop(listeners);
+
+    if success {
+        request.result().map_err(js_error)
+    } else {
+        Err(format!("{:?}", request.error()))
+    }
+}
+
+fn js_error(e: JsValue) -> String {
+    format!("{:?}", e)
+}
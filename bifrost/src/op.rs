@@ -1,8 +1,100 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
 pub trait Op {
     type Output;
+    type Error;
+
+    /// Whether executing the op more than once has the same effect as executing it once, which
+    /// allows dispatchers to retry it.
+    const IDEMPOTENT: bool = false;
+
+    /// How long dispatchers with a cache may reuse outputs of the op, for ops whose output only
+    /// depends on their input.
+    const CACHE_TTL: Option<Duration> = None;
+
+    /// Id of the heimdall module executing the op, to which dispatchers route it instead of their
+    /// own module.
+    const MODULE: Option<&'static str> = None;
+
+    /// Entry of the op in the manifest that `bifrost::entrypoint!` embeds in guest modules: a
+    /// JSON object describing the op, followed by a newline. Filled in by `#[bifrost::op]`, ops
+    /// leaving it empty are not listed in the manifest.
+    const MANIFEST: &'static str = "";
+
+    /// Id of the op, by which guests find the op to execute. Being a constant, it lets
+    /// `bifrost::entrypoint!` reject duplicate ids at compile time. Filled in by `#[bifrost::op]`.
+    const ID: &'static str;
+
+    /// The id of the op, `Self::ID`.
+    fn id() -> &'static str {
+        Self::ID
+    }
+
+    /// Executes the op, which guests deserialize for the purpose and can therefore consume.
+    #[cfg(any(feature = "remote", feature = "debug"))]
+    fn execute(self) -> Result<Self::Output, Self::Error>;
+}
+
+/// An op producing its output as a sequence of items, delivered to the client as they are
+/// emitted rather than once the op completes.
+pub trait StreamingOp {
+    type Item;
+    type Error;
+
+    /// Whether the op can be retried, see `Op::IDEMPOTENT`. Only requests failing before any
+    /// item is received are retried.
+    const IDEMPOTENT: bool = false;
 
-    fn id() -> &'static str;
+    /// Id of the heimdall module executing the op, see `Op::MODULE`.
+    const MODULE: Option<&'static str> = None;
+
+    /// Entry of the op in the manifest of guest modules, see `Op::MANIFEST`.
+    const MANIFEST: &'static str = "";
+
+    /// Id of the op, see `Op::ID`.
+    const ID: &'static str;
+
+    /// The id of the op, `Self::ID`.
+    fn id() -> &'static str {
+        Self::ID
+    }
 
     #[cfg(any(feature = "remote", feature = "debug"))]
-    fn execute(&self) -> Self::Output;
+    fn execute(&self, items: &mut Emitter<Self::Item>) -> Result<(), Self::Error>;
+}
+
+/// Receives the items of a `StreamingOp`.
+#[cfg(any(feature = "remote", feature = "debug"))]
+pub struct Emitter<'a, T> {
+    emit: &'a mut dyn FnMut(T),
+}
+
+#[cfg(any(feature = "remote", feature = "debug"))]
+impl<'a, T> Emitter<'a, T> {
+    pub(crate) fn new(emit: &'a mut dyn FnMut(T)) -> Self {
+        Emitter { emit }
+    }
+
+    pub fn emit(&mut self, item: T) {
+        (self.emit)(item)
+    }
+}
+
+/// Error type for ops that cannot fail.
+#[derive(Debug, Deserialize, Serialize)]
+pub enum Never {}
+
+/// No value is valid, as there is none.
+impl JsonSchema for Never {
+    fn schema_name() -> String {
+        "Never".to_string()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        Schema::Bool(false)
+    }
 }
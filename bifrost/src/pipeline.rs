@@ -0,0 +1,238 @@
+use crate::batch::Target;
+use crate::dispatcher::Response;
+use crate::format::Format;
+use crate::op::Op;
+#[cfg(feature = "debug")]
+use crate::protocol::{self, Handler};
+use crate::protocol::{Binding, Outcome, Stage, StageFailure};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+/// A chain of ops executed one after the other within a single request, later ops taking fields
+/// of their input from the outputs of earlier ones. Created by `Dispatcher::pipeline` and sent
+/// with `Dispatcher::send_pipeline`.
+///
+/// Fields are bound through JSON pointers into the JSON representation of ops and outputs:
+///
+/// ```ignore
+/// let pipeline = dispatcher.pipeline(&LookupUser { email });
+/// let user = pipeline.step();
+/// let pipeline = pipeline
+///     .then(&LoadDocuments { user_id: String::new() })
+///     .bind("/user_id", user, "/id");
+/// ```
+pub struct Pipeline<T> {
+    target: Target,
+    entries: Vec<Entry>,
+    last: PhantomData<fn() -> T>,
+}
+
+struct Entry {
+    op: &'static str,
+    input: Result<Vec<u8>, String>,
+    #[cfg_attr(
+        not(any(feature = "local-native", feature = "local-browser")),
+        allow(dead_code)
+    )]
+    idempotent: bool,
+    bindings: Vec<Binding>,
+    #[cfg(feature = "debug")]
+    exec: Handler,
+}
+
+/// Identifies a step of a pipeline.
+pub struct Step<T> {
+    index: usize,
+    op: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Step<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Step<T> {}
+
+/// The first failed step of a pipeline.
+#[derive(Debug)]
+pub struct PipelineError {
+    step: usize,
+    outcome: Outcome,
+    format: Format,
+}
+
+impl<T> Pipeline<T>
+where
+    T: Op + Serialize + DeserializeOwned,
+    T::Output: Serialize,
+    T::Error: Serialize,
+{
+    pub(crate) fn new(op: &T, module: Option<&str>) -> Self {
+        let mut target = Target::new(module);
+        let entry = Entry::new(op, &mut target);
+
+        Pipeline {
+            target,
+            entries: vec![entry],
+            last: PhantomData,
+        }
+    }
+
+    /// Appends an op, executed with the output of the previous steps at hand. An op executed by
+    /// another module than the first step fails the pipeline with a `ParseError` when sent.
+    pub fn then<U>(mut self, op: &U) -> Pipeline<U>
+    where
+        U: Op + Serialize + DeserializeOwned,
+        U::Output: Serialize,
+        U::Error: Serialize,
+    {
+        let entry = Entry::new(op, &mut self.target);
+        self.entries.push(entry);
+
+        Pipeline {
+            target: self.target,
+            entries: self.entries,
+            last: PhantomData,
+        }
+    }
+}
+
+impl<T> Pipeline<T> {
+    /// The last step added so far.
+    pub fn step(&self) -> Step<T> {
+        Step {
+            index: self.entries.len() - 1,
+            op: PhantomData,
+        }
+    }
+
+    /// Sets the value at the JSON pointer `target` in the input of the last step to the value at
+    /// `source` in the output of `step`, an empty pointer standing for the whole value.
+    pub fn bind<S>(mut self, target: &str, step: Step<S>, source: &str) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.bindings.push(Binding {
+                target: target.to_string(),
+                step: step.index,
+                source: source.to_string(),
+            });
+        }
+
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the pipeline can be retried, which requires all of its ops to be idempotent.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    pub(crate) fn is_idempotent(&self) -> bool {
+        self.entries.iter().all(|entry| entry.idempotent)
+    }
+
+    /// Module the pipeline is sent to, see `Target`.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    pub(crate) fn module(&self) -> Option<&str> {
+        self.target.module()
+    }
+
+    pub(crate) fn stages(&self) -> Result<Vec<Stage>, String> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                Ok(Stage {
+                    op: entry.op.to_string(),
+                    input: entry.input.clone()?,
+                    bindings: entry.bindings.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Looks up how to execute an op of the pipeline in place, as a guest would.
+    #[cfg(feature = "debug")]
+    pub(crate) fn handler(&self, op: &str) -> Option<Handler> {
+        self.entries
+            .iter()
+            .find(|entry| entry.op == op)
+            .map(|entry| entry.exec)
+    }
+
+    /// Decodes the failure of a step, reported by the guest in `format`.
+    pub(crate) fn failed<O>(
+        &self,
+        format: Format,
+        failure: StageFailure,
+    ) -> Response<O, PipelineError> {
+        let format = if failure.step + 1 == self.entries.len() {
+            format
+        } else {
+            Format::Json
+        };
+
+        Response::OpError(PipelineError {
+            step: failure.step,
+            outcome: failure.outcome,
+            format,
+        })
+    }
+}
+
+impl Entry {
+    fn new<T>(op: &T, target: &mut Target) -> Self
+    where
+        T: Op + Serialize + DeserializeOwned,
+        T::Output: Serialize,
+        T::Error: Serialize,
+    {
+        let input = target
+            .admit(<T as Op>::id(), T::MODULE)
+            .and_then(|()| Format::Json.serialize(op));
+
+        Entry {
+            op: <T as Op>::id(),
+            input,
+            idempotent: T::IDEMPOTENT,
+            bindings: Vec::new(),
+            #[cfg(feature = "debug")]
+            exec: protocol::exec::<T>,
+        }
+    }
+}
+
+impl PipelineError {
+    /// Index of the failed step.
+    pub fn step(&self) -> usize {
+        self.step
+    }
+
+    /// Returns the error of `step` if it is the one that failed, with an error of its own.
+    pub fn op_error<T>(&self, step: Step<T>) -> Option<T::Error>
+    where
+        T: Op,
+        T::Error: DeserializeOwned,
+    {
+        match &self.outcome {
+            Outcome::OpError(bytes) if step.index == self.step => {
+                self.format.deserialize(bytes).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Describes failures other than op errors, such as a field that could not be bound.
+    pub fn message(&self) -> Option<&str> {
+        match &self.outcome {
+            Outcome::Success(_) | Outcome::OpError(_) => None,
+            Outcome::UnknownOp(e)
+            | Outcome::InputDecodeError(e)
+            | Outcome::OutputEncodeError(e) => Some(e),
+        }
+    }
+}
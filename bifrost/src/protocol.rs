@@ -0,0 +1,332 @@
+use crate::format::Format;
+#[cfg(any(feature = "remote", feature = "debug"))]
+use crate::op::Op;
+#[cfg(any(feature = "remote", feature = "debug"))]
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Prefix of the labels under which guests execute something else than a single op, which op
+/// ids cannot start with.
+pub const RESERVED_PREFIX: &str = "__bifrost_";
+
+/// Label under which a guest executes a batch of ops rather than a single one.
+pub const BATCH_LABEL: &str = "__bifrost_batch";
+
+/// Label under which a guest executes a pipeline of ops rather than a single one.
+pub const PIPELINE_LABEL: &str = "__bifrost_pipeline";
+
+/// Label under which a guest writes the JSON schemas of the types of its ops rather than executing
+/// one, as a JSON object keyed on op ids.
+pub const SCHEMAS_LABEL: &str = "__bifrost_schemas";
+
+/// Content type of streamed outputs, one `Frame` serialized as JSON per line.
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// Kind of the error reported by heimdall when the deadline of a request passes during its
+/// execution.
+pub const DEADLINE_EXCEEDED: &str = "deadline_exceeded";
+
+/// Executes an op serialized in the first format, serializing its result in the second.
+pub type Handler = fn(&[u8], Format, Format) -> Outcome;
+
+/// A serialized op within a batch.
+///
+/// A batch payload is a list of calls, and its output the list of their outcomes in the same
+/// order, both serialized in the negotiated formats.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Call {
+    pub op: String,
+    #[serde(with = "serde_bytes")]
+    pub payload: Vec<u8>,
+}
+
+/// Serialized result of executing an op.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Outcome {
+    Success(#[serde(with = "serde_bytes")] Vec<u8>),
+    OpError(#[serde(with = "serde_bytes")] Vec<u8>),
+    UnknownOp(String),
+    InputDecodeError(String),
+    OutputEncodeError(String),
+}
+
+/// A line of a streamed output. An `Error` or `Failure` frame ends the stream.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Frame<T, E> {
+    Item(T),
+    Error(E),
+    /// Failure of the execution itself, added by heimdall along with the status it would have
+    /// responded with had the output not been streamed.
+    Failure {
+        status: u16,
+        kind: String,
+        message: String,
+    },
+}
+
+/// A step of a pipeline: an op serialized as JSON, some of whose fields are replaced by outputs
+/// of earlier steps before it is executed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Stage {
+    pub op: String,
+    #[serde(with = "serde_bytes")]
+    pub input: Vec<u8>,
+    pub bindings: Vec<Binding>,
+}
+
+/// Replaces the value at the JSON pointer `target` in the input of a stage with the value at
+/// `source` in the output of step `step`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Binding {
+    pub target: String,
+    pub step: usize,
+    pub source: String,
+}
+
+/// The first failed step of a pipeline, reported by the guest as an op error.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct StageFailure {
+    pub step: usize,
+    pub outcome: Outcome,
+}
+
+/// Executes an op serialized in the input format, serializing its result in the output format.
+#[cfg(any(feature = "remote", feature = "debug"))]
+pub fn exec<T>(payload: &[u8], input: Format, output: Format) -> Outcome
+where
+    T: Op + DeserializeOwned,
+    T::Output: Serialize,
+    T::Error: Serialize,
+{
+    let op: T = match input.deserialize(payload) {
+        Ok(op) => op,
+        Err(e) => return Outcome::InputDecodeError(e),
+    };
+
+    let result = match op.execute() {
+        Ok(v) => output.serialize(&v).map(Outcome::Success),
+        Err(e) => output.serialize(&e).map(Outcome::OpError),
+    };
+
+    result.unwrap_or_else(Outcome::OutputEncodeError)
+}
+
+/// Runs the stages of a pipeline in order, returning the output of the last one serialized in
+/// `output`, or the first failure.
+///
+/// Outputs of intermediate steps are serialized as JSON so that they can be bound into later
+/// inputs, which also applies to their op errors.
+pub fn run_pipeline<F>(stages: &[Stage], find: F, output: Format) -> Result<Vec<u8>, StageFailure>
+where
+    F: Fn(&str) -> Option<Handler>,
+{
+    let mut outputs: Vec<Value> = Vec::with_capacity(stages.len());
+
+    for (step, stage) in stages.iter().enumerate() {
+        let fail = |outcome| StageFailure { step, outcome };
+
+        let handler = find(&stage.op)
+            .ok_or_else(|| fail(Outcome::UnknownOp(format!("unknown op: {}", stage.op))))?;
+        let input = bind(stage, &outputs).map_err(|e| fail(Outcome::InputDecodeError(e)))?;
+
+        if step + 1 == stages.len() {
+            return match handler(&input, Format::Json, output) {
+                Outcome::Success(bytes) => Ok(bytes),
+                outcome => Err(fail(outcome)),
+            };
+        }
+
+        match handler(&input, Format::Json, Format::Json) {
+            Outcome::Success(bytes) => outputs.push(
+                serde_json::from_slice(&bytes)
+                    .map_err(|e| fail(Outcome::OutputEncodeError(e.to_string())))?,
+            ),
+            outcome => return Err(fail(outcome)),
+        }
+    }
+
+    Err(StageFailure {
+        step: 0,
+        outcome: Outcome::InputDecodeError("empty pipeline".to_string()),
+    })
+}
+
+/// Input of a stage, with its bindings replaced by the outputs of earlier steps.
+pub(crate) fn bind(stage: &Stage, outputs: &[Value]) -> Result<Vec<u8>, String> {
+    if stage.bindings.is_empty() {
+        return Ok(stage.input.clone());
+    }
+
+    let mut input: Value = serde_json::from_slice(&stage.input).map_err(|e| e.to_string())?;
+
+    for binding in stage.bindings.iter() {
+        let value = outputs
+            .get(binding.step)
+            .ok_or_else(|| format!("step {} has no output to bind", binding.step))?
+            .pointer(&binding.source)
+            .ok_or_else(|| {
+                format!(
+                    "no value at \"{}\" in output of step {}",
+                    binding.source, binding.step
+                )
+            })?;
+
+        let target = input
+            .pointer_mut(&binding.target)
+            .ok_or_else(|| format!("no value at \"{}\" in input", binding.target))?;
+
+        *target = value.clone();
+    }
+
+    serde_json::to_vec(&input).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stage(op: &str, input: Value, bindings: &[(&str, usize, &str)]) -> Stage {
+        Stage {
+            op: op.to_string(),
+            input: serde_json::to_vec(&input).unwrap(),
+            bindings: bindings
+                .iter()
+                .map(|(target, step, source)| Binding {
+                    target: target.to_string(),
+                    step: *step,
+                    source: source.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    fn bound(stage: &Stage, outputs: &[Value]) -> Result<Value, String> {
+        bind(stage, outputs).map(|input| serde_json::from_slice(&input).unwrap())
+    }
+
+    /// Takes `{"i": n}` and outputs `{"i": n + 1}`.
+    fn increment(input: &[u8], from: Format, to: Format) -> Outcome {
+        let input: Value = from.deserialize(input).unwrap();
+        match input["i"].as_i64() {
+            Some(i) => Outcome::Success(to.serialize(&json!({ "i": i + 1 })).unwrap()),
+            None => Outcome::OpError(to.serialize("not a number").unwrap()),
+        }
+    }
+
+    fn find(op: &str) -> Option<Handler> {
+        match op {
+            "increment" => Some(increment),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn stages_without_bindings_are_left_untouched() {
+        let stage = Stage {
+            op: "increment".to_string(),
+            input: b"not even json".to_vec(),
+            bindings: Vec::new(),
+        };
+
+        assert_eq!(bind(&stage, &[]), Ok(b"not even json".to_vec()));
+    }
+
+    #[test]
+    fn bindings_replace_values_at_their_target() {
+        let stage = stage(
+            "charge",
+            json!({ "user": { "id": 0 }, "amount": 0, "note": "kept" }),
+            &[("/user/id", 0, "/id"), ("/amount", 1, "/items/1/price")],
+        );
+        let outputs = [
+            json!({ "id": 7 }),
+            json!({ "items": [{ "price": 1 }, { "price": 2 }] }),
+        ];
+
+        assert_eq!(
+            bound(&stage, &outputs),
+            Ok(json!({ "user": { "id": 7 }, "amount": 2, "note": "kept" }))
+        );
+    }
+
+    #[test]
+    fn empty_pointers_stand_for_the_whole_value() {
+        let whole_output = stage("greet", json!({ "name": null }), &[("/name", 0, "")]);
+        assert_eq!(
+            bound(&whole_output, &[json!("Bifrost")]),
+            Ok(json!({ "name": "Bifrost" }))
+        );
+
+        let whole_input = stage("greet", json!(null), &[("", 0, "/user")]);
+        assert_eq!(
+            bound(&whole_input, &[json!({ "user": { "name": "Bifrost" } })]),
+            Ok(json!({ "name": "Bifrost" }))
+        );
+    }
+
+    #[test]
+    fn unresolved_bindings_are_reported() {
+        let outputs = [json!({ "id": 7 })];
+
+        let later_step = stage("charge", json!({ "id": 0 }), &[("/id", 1, "/id")]);
+        assert!(bind(&later_step, &outputs).is_err());
+
+        let missing_source = stage("charge", json!({ "id": 0 }), &[("/id", 0, "/name")]);
+        assert!(bind(&missing_source, &outputs).is_err());
+
+        let missing_target = stage("charge", json!({ "id": 0 }), &[("/user", 0, "/id")]);
+        assert!(bind(&missing_target, &outputs).is_err());
+    }
+
+    #[test]
+    fn pipelines_chain_outputs_and_serialize_the_last_one() {
+        let stages = [
+            stage("increment", json!({ "i": 1 }), &[]),
+            stage("increment", json!({ "i": 0 }), &[("/i", 0, "/i")]),
+            stage("increment", json!({ "i": 0 }), &[("/i", 1, "/i")]),
+        ];
+
+        let output = run_pipeline(&stages, find, Format::Cbor).unwrap();
+        assert_eq!(
+            Format::Cbor.deserialize::<Value>(&output),
+            Ok(json!({ "i": 4 }))
+        );
+    }
+
+    #[test]
+    fn pipelines_stop_at_the_first_failed_step() {
+        let stages = [
+            stage("increment", json!({ "i": 1 }), &[]),
+            stage("unknown", json!({}), &[]),
+            stage("increment", json!({ "i": 0 }), &[]),
+        ];
+        let failure = run_pipeline(&stages, find, Format::Json).unwrap_err();
+        assert_eq!(failure.step, 1);
+        assert!(matches!(failure.outcome, Outcome::UnknownOp(_)));
+
+        let stages = [
+            stage("increment", json!({ "i": "one" }), &[]),
+            stage("increment", json!({ "i": 0 }), &[("/i", 0, "/i")]),
+        ];
+        let failure = run_pipeline(&stages, find, Format::Json).unwrap_err();
+        assert_eq!(failure.step, 0);
+        assert!(matches!(failure.outcome, Outcome::OpError(_)));
+
+        let stages = [
+            stage("increment", json!({ "i": 1 }), &[]),
+            stage("increment", json!({ "i": 0 }), &[("/i", 0, "/missing")]),
+        ];
+        let failure = run_pipeline(&stages, find, Format::Json).unwrap_err();
+        assert_eq!(failure.step, 1);
+        assert!(matches!(failure.outcome, Outcome::InputDecodeError(_)));
+    }
+
+    #[test]
+    fn empty_pipelines_fail() {
+        let failure = run_pipeline(&[], find, Format::Json).unwrap_err();
+        assert!(matches!(failure.outcome, Outcome::InputDecodeError(_)));
+    }
+}
@@ -0,0 +1,243 @@
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::exchange::{Handling, Head};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::time;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// When and how often a dispatcher retries requests for idempotent ops, see `Op::IDEMPOTENT`.
+///
+/// Requests are retried on network errors and on responses whose status is in one of the
+/// retryable classes, 5xx by default. Op errors are never retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    statuses: Vec<RangeInclusive<u16>>,
+}
+
+impl RetryPolicy {
+    /// Makes up to `max_attempts` attempts, the first one included, backing off exponentially
+    /// from 100ms up to 10s between them.
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+            statuses: vec![500..=599],
+        }
+    }
+
+    /// Sets the delay before the first retry, doubled for each further one up to `max`.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Enables or disables jitter, which picks each delay at random up to the backoff.
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Replaces the retryable status classes, such as `500..=599` or `429..=429`.
+    pub fn with_statuses(mut self, statuses: Vec<RangeInclusive<u16>>) -> Self {
+        self.statuses = statuses;
+        self
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Whether to retry a request after its `attempt`th attempt got `reply`, or a network error if
+    /// `None`, for a call whose deadline is `deadline`.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    fn retries(&self, attempt: u32, reply: Option<Head>, deadline: Option<u64>) -> bool {
+        let retryable = match reply.map(|head| head.handling()) {
+            None => true,
+            Some(Handling::Rejected(status)) => self.statuses.iter().any(|s| s.contains(&status)),
+            Some(_) => false,
+        };
+
+        // A retry would be sent with a deadline that already passed.
+        let expired = matches!(deadline, Some(deadline) if deadline <= time::now_millis());
+
+        retryable && !expired && attempt < self.max_attempts
+    }
+
+    /// Delay before the given retry, starting at 1.
+    #[cfg(any(feature = "local-native", feature = "local-browser"))]
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .checked_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+
+        if !self.jitter {
+            return backoff;
+        }
+
+        backoff.mul_f64(random())
+    }
+}
+
+/// Attempts at sending a request, deciding after each one whether to make another, which is only
+/// the case for idempotent requests of dispatchers with a retry policy.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) struct Retries<'a> {
+    policy: Option<&'a RetryPolicy>,
+    attempt: u32,
+    deadline: Option<u64>,
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+impl<'a> Retries<'a> {
+    pub(crate) fn new(
+        policy: Option<&'a RetryPolicy>,
+        idempotent: bool,
+        deadline: Option<u64>,
+    ) -> Self {
+        Retries {
+            policy: policy.filter(|_| idempotent),
+            attempt: 1,
+            deadline,
+        }
+    }
+
+    /// Whether the request may be retried at all, or is sent once whatever happens.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    /// Delay before retrying the request after an attempt got `reply`, or a network error if
+    /// `None`, unless it is not to be retried. Other errors, such as an open circuit, are final.
+    pub(crate) fn after(&mut self, reply: Option<Head>) -> Option<Duration> {
+        let policy = self.policy?;
+
+        if !policy.retries(self.attempt, reply, self.deadline) {
+            return None;
+        }
+
+        let backoff = policy.backoff(self.attempt);
+        self.attempt += 1;
+        Some(backoff)
+    }
+}
+
+/// A random number in `[0, 1)`, which differs across clients so that they do not retry in
+/// lockstep.
+#[cfg(feature = "local-native")]
+fn random() -> f64 {
+    fastrand::f64()
+}
+
+/// A random number in `[0, 1)`, which differs across clients so that they do not retry in
+/// lockstep.
+#[cfg(all(feature = "local-browser", not(feature = "local-native")))]
+fn random() -> f64 {
+    js_sys::Math::random()
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(3)
+    }
+}
+
+#[cfg(all(test, any(feature = "local-native", feature = "local-browser")))]
+mod tests {
+    use super::*;
+    use crate::dispatcher::{ERROR_HEADER, OP_ERROR};
+    use crate::protocol::DEADLINE_EXCEEDED;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new(3)
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(300))
+            .with_jitter(false)
+    }
+
+    fn error(kind: &str) -> Vec<(String, String)> {
+        vec![(ERROR_HEADER.to_string(), kind.to_string())]
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_maximum() {
+        let policy = policy();
+
+        let backoffs: Vec<_> = (1..=4)
+            .map(|retry| policy.backoff(retry).as_millis())
+            .collect();
+
+        assert_eq!(backoffs, vec![100, 200, 300, 300]);
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn jitter_stays_within_the_backoff() {
+        let policy = policy().with_jitter(true);
+
+        for retry in 1..=4 {
+            for _ in 0..100 {
+                assert!(policy.backoff(retry) <= policy.clone().with_jitter(false).backoff(retry));
+            }
+        }
+    }
+
+    #[test]
+    fn retries_network_errors_and_retryable_statuses() {
+        let policy = policy();
+
+        assert!(policy.retries(1, None, None));
+        assert!(policy.retries(1, Some(Head::new(503, &[])), None));
+
+        let policy = policy.with_statuses(vec![429..=429]);
+        assert!(policy.retries(1, Some(Head::new(429, &[])), None));
+        assert!(!policy.retries(1, Some(Head::new(503, &[])), None));
+    }
+
+    #[test]
+    fn does_not_retry_executions_or_client_errors() {
+        let policy = policy();
+        let op_error = error(OP_ERROR);
+        let deadline_exceeded = error(DEADLINE_EXCEEDED);
+
+        assert!(!policy.retries(1, Some(Head::new(200, &[])), None));
+        assert!(!policy.retries(1, Some(Head::new(422, &op_error)), None));
+        assert!(!policy.retries(1, Some(Head::new(504, &deadline_exceeded)), None));
+        assert!(!policy.retries(1, Some(Head::new(400, &[])), None));
+    }
+
+    #[test]
+    fn stops_after_the_last_attempt() {
+        let policy = policy();
+        let mut retries = Retries::new(Some(&policy), true, None);
+
+        assert_eq!(retries.after(None), Some(Duration::from_millis(100)));
+        assert_eq!(retries.after(None), Some(Duration::from_millis(200)));
+        assert_eq!(retries.after(None), None);
+    }
+
+    #[test]
+    fn stops_once_the_deadline_passed() {
+        let policy = policy();
+        let mut retries = Retries::new(Some(&policy), true, Some(time::now_millis()));
+
+        assert_eq!(retries.after(None), None);
+    }
+
+    #[test]
+    fn only_retries_idempotent_requests() {
+        let policy = policy();
+        let mut retries = Retries::new(Some(&policy), false, None);
+
+        assert!(!retries.is_enabled());
+        assert_eq!(retries.after(None), None);
+        assert!(!Retries::new(None, true, None).is_enabled());
+    }
+}
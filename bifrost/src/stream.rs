@@ -0,0 +1,174 @@
+use crate::dispatcher::Response;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use crate::protocol::{Frame, DEADLINE_EXCEEDED};
+use futures::stream::LocalBoxStream;
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use futures::stream::{self, Stream, StreamExt};
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use serde::de::DeserializeOwned;
+
+/// Items of a `StreamingOp` as they are received, ending after the first error.
+pub type ItemStream<T, E> = LocalBoxStream<'static, Response<T, E>>;
+
+/// A stream holding a single response, for requests failing before any item is received.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) fn once<T, E>(response: Response<T, E>) -> ItemStream<T, E>
+where
+    T: 'static,
+    E: 'static,
+{
+    stream::once(async move { response }).boxed_local()
+}
+
+/// Splits chunks of a response body into frames, one per line.
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) fn decode<T, E, S>(chunks: S) -> ItemStream<T, E>
+where
+    T: DeserializeOwned + 'static,
+    E: DeserializeOwned + 'static,
+    S: Stream<Item = Result<Vec<u8>, String>> + 'static,
+{
+    let state = Some((chunks.boxed_local(), Vec::new()));
+
+    stream::unfold(state, |state| async move {
+        let (mut chunks, mut buffer) = state?;
+
+        loop {
+            if let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+
+                let response = parse(&line);
+                let state = match response {
+                    Response::Success(_) => Some((chunks, buffer)),
+                    _ => None,
+                };
+
+                return Some((response, state));
+            }
+
+            match chunks.next().await {
+                Some(Ok(chunk)) => buffer.extend(chunk),
+                Some(Err(e)) => return Some((Response::NetworkError(e), None)),
+                None if buffer.iter().all(u8::is_ascii_whitespace) => return None,
+                None => return Some((parse(&buffer), None)),
+            }
+        }
+    })
+    .boxed_local()
+}
+
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+pub(crate) fn parse<T, E>(line: &[u8]) -> Response<T, E>
+where
+    T: DeserializeOwned,
+    E: DeserializeOwned,
+{
+    match serde_json::from_slice(line) {
+        Ok(Frame::Item(item)) => Response::Success(item),
+        Ok(Frame::Error(e)) => Response::OpError(e),
+        Ok(Frame::Failure { kind, .. }) if kind == DEADLINE_EXCEEDED => Response::Timeout,
+        Ok(Frame::Failure {
+            status, message, ..
+        }) => Response::RequestError(status, message),
+        Err(e) => Response::ParseError(e.to_string()),
+    }
+}
+
+#[cfg(all(test, any(feature = "local-native", feature = "local-browser")))]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    fn frames(chunks: &[&str]) -> Vec<Response<u32, String>> {
+        let chunks: Vec<Result<Vec<u8>, String>> = chunks
+            .iter()
+            .map(|chunk| Ok(chunk.as_bytes().to_vec()))
+            .collect();
+
+        block_on(decode(stream::iter(chunks)).collect())
+    }
+
+    fn items(responses: &[Response<u32, String>]) -> Vec<u32> {
+        responses
+            .iter()
+            .map(|response| match response {
+                Response::Success(item) => *item,
+                other => panic!("unexpected response: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn frames_split_across_chunks() {
+        let responses = frames(&[r#"{"it"#, r#"em":1}"#, "\n", r#"{"item":2}"#, "\n"]);
+        assert_eq!(items(&responses), [1, 2]);
+    }
+
+    #[test]
+    fn several_frames_in_one_chunk() {
+        let responses = frames(&["{\"item\":1}\n{\"item\":2}\n{\"item\":3}\n"]);
+        assert_eq!(items(&responses), [1, 2, 3]);
+    }
+
+    #[test]
+    fn blank_lines_are_skipped() {
+        let responses = frames(&["\n{\"item\":1}\n\r\n", "  \n{\"item\":2}\n\n"]);
+        assert_eq!(items(&responses), [1, 2]);
+    }
+
+    #[test]
+    fn trailing_line_without_newline() {
+        let responses = frames(&["{\"item\":1}\n{\"item\"", ":2}"]);
+        assert_eq!(items(&responses), [1, 2]);
+    }
+
+    #[test]
+    fn truncated_trailing_line() {
+        let responses = frames(&["{\"item\":1}\n{\"item\":"]);
+
+        assert_eq!(responses.len(), 2);
+        assert!(matches!(responses[0], Response::Success(1)));
+        assert!(matches!(responses[1], Response::ParseError(_)));
+    }
+
+    #[test]
+    fn error_frames_end_the_stream() {
+        let responses = frames(&["{\"item\":1}\n{\"error\":\"boom\"}\n{\"item\":2}\n"]);
+
+        assert_eq!(responses.len(), 2);
+        assert!(matches!(&responses[1], Response::OpError(e) if e == "boom"));
+    }
+
+    #[test]
+    fn failure_frames() {
+        let deadline = frames(&[concat!(
+            r#"{"failure":{"status":504,"kind":"deadline_exceeded","#,
+            r#""message":"deadline exceeded"}}"#,
+            "\n",
+        )]);
+        assert!(matches!(deadline[..], [Response::Timeout]));
+
+        let panic = frames(&[concat!(
+            r#"{"failure":{"status":500,"kind":"panic","message":"oops"}}"#,
+            "\n",
+        )]);
+        assert!(matches!(&panic[..], [Response::RequestError(500, m)] if m == "oops"));
+    }
+
+    #[test]
+    fn network_errors_end_the_stream() {
+        let chunks = vec![
+            Ok(b"{\"item\":1}\n{\"item\"".to_vec()),
+            Err("connection reset".to_string()),
+            Ok(b":2}\n".to_vec()),
+        ];
+        let responses: Vec<Response<u32, String>> =
+            block_on(decode(stream::iter(chunks)).collect());
+
+        assert_eq!(responses.len(), 2);
+        assert!(matches!(&responses[1], Response::NetworkError(e) if e == "connection reset"));
+    }
+}
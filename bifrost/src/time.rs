@@ -0,0 +1,27 @@
+#[cfg(any(feature = "local-native", feature = "local-browser"))]
+use std::time::Duration;
+
+/// Current time, in milliseconds since the unix epoch.
+#[cfg(not(all(feature = "local-browser", not(feature = "local-native"))))]
+pub(crate) fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Current time, in milliseconds since the unix epoch.
+#[cfg(all(feature = "local-browser", not(feature = "local-native")))]
+pub(crate) fn now_millis() -> u64 {
+    js_sys::Date::now() as u64
+}
+
+#[cfg(feature = "local-native")]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+#[cfg(all(feature = "local-browser", not(feature = "local-native")))]
+pub(crate) async fn sleep(duration: Duration) {
+    gloo::timers::future::sleep(duration).await
+}
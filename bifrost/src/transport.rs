@@ -0,0 +1,76 @@
+#[cfg(feature = "local-browser")]
+pub mod fetch;
+#[cfg(feature = "local-native")]
+pub mod http;
+#[cfg(feature = "in-process")]
+pub mod in_process;
+#[cfg(all(unix, feature = "unix-socket"))]
+pub mod unix;
+
+use crate::exchange::Head;
+use futures::StreamExt;
+
+/// Body of a reply, received in chunks.
+#[cfg(not(target_arch = "wasm32"))]
+pub type Body = futures::stream::BoxStream<'static, Result<Vec<u8>, String>>;
+
+/// Body of a reply, received in chunks.
+#[cfg(target_arch = "wasm32")]
+pub type Body = futures::stream::LocalBoxStream<'static, Result<Vec<u8>, String>>;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub type BoxFuture<'a, T> = futures::future::BoxFuture<'a, T>;
+
+#[cfg(target_arch = "wasm32")]
+pub type BoxFuture<'a, T> = futures::future::LocalBoxFuture<'a, T>;
+
+/// Carries requests from a `Dispatcher` to heimdall, and heimdall's replies back.
+///
+/// Requests are always `POST`s to a heimdall route, such as `/:module_id/execute`.
+pub trait Transport: Send + Sync {
+    fn send(&self, request: Request) -> BoxFuture<'_, Result<Reply, String>>;
+}
+
+#[derive(Clone)]
+pub struct Request {
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Body,
+}
+
+impl Reply {
+    pub(crate) fn head(&self) -> Head<'_> {
+        Head::new(self.status, &self.headers)
+    }
+
+    /// Value of a header, whose name is compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub async fn bytes(mut self) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+
+        while let Some(chunk) = self.body.next().await {
+            bytes.extend(chunk?);
+        }
+
+        Ok(bytes)
+    }
+
+    pub async fn text(self) -> String {
+        self.bytes()
+            .await
+            .map(|b| String::from_utf8_lossy(&b).into_owned())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,70 @@
+use crate::transport::{BoxFuture, Reply, Request, Transport};
+use futures::{FutureExt, StreamExt};
+use wasm_bindgen::JsCast;
+
+/// Sends requests with the browser's `fetch`.
+///
+/// Requests are aborted when their future, or the body of their reply, is dropped.
+pub struct FetchTransport {
+    origin: String,
+}
+
+/// Aborts a request when dropped.
+struct Abort(web_sys::AbortController);
+
+impl FetchTransport {
+    /// Creates a transport to the heimdall instance at `origin`, such as `http://localhost:8080`.
+    pub fn new(origin: &str) -> Self {
+        FetchTransport {
+            origin: origin.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl Transport for FetchTransport {
+    fn send(&self, request: Request) -> BoxFuture<'_, Result<Reply, String>> {
+        async move {
+            let abort = Abort(web_sys::AbortController::new().map_err(|e| format!("{:?}", e))?);
+
+            let mut builder =
+                gloo_net::http::Request::post(&format!("{}{}", self.origin, request.path))
+                    .abort_signal(Some(&abort.0.signal()));
+
+            for (name, value) in request.headers.iter() {
+                builder = builder.header(name, value);
+            }
+
+            let resp = builder
+                .body(js_sys::Uint8Array::from(request.body.as_slice()))
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            let body = match resp.body() {
+                None => futures::stream::empty().boxed_local(),
+                Some(body) => wasm_streams::ReadableStream::from_raw(body.unchecked_into())
+                    .into_stream()
+                    .map(move |chunk| {
+                        let _ = &abort;
+                        chunk
+                            .map(|c| js_sys::Uint8Array::new(&c).to_vec())
+                            .map_err(|e| format!("{:?}", e))
+                    })
+                    .boxed_local(),
+            };
+
+            Ok(Reply {
+                status: resp.status(),
+                headers: resp.headers().entries().collect(),
+                body,
+            })
+        }
+        .boxed_local()
+    }
+}
+
+impl Drop for Abort {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
@@ -0,0 +1,61 @@
+use crate::transport::{BoxFuture, Reply, Request, Transport};
+use futures::{FutureExt, StreamExt};
+
+/// Sends requests over HTTP with `reqwest`, reusing connections across requests.
+pub struct HttpTransport {
+    client: reqwest::Client,
+    origin: String,
+}
+
+impl HttpTransport {
+    /// Creates a transport to the heimdall instance at `origin`, such as `http://localhost:8080`.
+    pub fn new(origin: &str) -> Self {
+        Self::with_client(reqwest::Client::new(), origin)
+    }
+
+    pub fn with_client(client: reqwest::Client, origin: &str) -> Self {
+        HttpTransport {
+            client,
+            origin: origin.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl Transport for HttpTransport {
+    fn send(&self, request: Request) -> BoxFuture<'_, Result<Reply, String>> {
+        async move {
+            let mut builder = self.client.post(format!("{}{}", self.origin, request.path));
+
+            for (name, value) in request.headers.iter() {
+                builder = builder.header(name, value);
+            }
+
+            let resp = builder
+                .body(request.body)
+                .send()
+                .await
+                .map_err(|e| e.to_string())?;
+
+            let headers = resp
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    value
+                        .to_str()
+                        .ok()
+                        .map(|v| (name.to_string(), v.to_string()))
+                })
+                .collect();
+
+            Ok(Reply {
+                status: resp.status().as_u16(),
+                headers,
+                body: resp
+                    .bytes_stream()
+                    .map(|chunk| chunk.map(|b| b.to_vec()).map_err(|e| e.to_string()))
+                    .boxed(),
+            })
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,73 @@
+use crate::transport::{BoxFuture, Reply, Request, Transport};
+use axum::body::HttpBody;
+use axum::extract::Extension;
+use axum::Router;
+use futures::FutureExt;
+use heimdall::registry::Registry;
+use std::sync::{Arc, Mutex};
+use tower::ServiceExt;
+
+/// Executes requests within the current process, routing them through heimdall's handlers
+/// against a `Registry` without going over the network.
+pub struct InProcessTransport {
+    // `Router` is not `Sync`, so it is cloned out for each request.
+    router: Mutex<Router>,
+}
+
+impl InProcessTransport {
+    pub fn new(registry: Registry) -> Self {
+        InProcessTransport {
+            router: Mutex::new(
+                heimdall::handlers::execution_routes().layer(Extension(Arc::new(registry))),
+            ),
+        }
+    }
+}
+
+impl Transport for InProcessTransport {
+    fn send(&self, request: Request) -> BoxFuture<'_, Result<Reply, String>> {
+        let router = match self.router.lock() {
+            Ok(router) => router.clone(),
+            Err(e) => e.into_inner().clone(),
+        };
+
+        async move {
+            let mut builder = axum::http::Request::post(request.path);
+
+            for (name, value) in request.headers.iter() {
+                builder = builder.header(name, value);
+            }
+
+            let req = builder
+                .body(axum::body::Body::from(request.body))
+                .map_err(|e| e.to_string())?;
+
+            let resp = router.oneshot(req).await.map_err(|e| e.to_string())?;
+
+            let headers = resp
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    value
+                        .to_str()
+                        .ok()
+                        .map(|v| (name.to_string(), v.to_string()))
+                })
+                .collect();
+
+            let status = resp.status().as_u16();
+            let body = futures::stream::unfold(resp.into_body(), |mut body| async move {
+                body.data()
+                    .await
+                    .map(|chunk| (chunk.map(|b| b.to_vec()).map_err(|e| e.to_string()), body))
+            });
+
+            Ok(Reply {
+                status,
+                headers,
+                body: Box::pin(body),
+            })
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,65 @@
+use crate::transport::{BoxFuture, Reply, Request, Transport};
+use futures::{FutureExt, StreamExt};
+use std::path::PathBuf;
+
+/// Sends requests over HTTP to a heimdall instance listening on a Unix domain socket, opening a
+/// connection per request.
+pub struct UnixTransport {
+    path: PathBuf,
+}
+
+impl UnixTransport {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        UnixTransport { path: path.into() }
+    }
+}
+
+impl Transport for UnixTransport {
+    fn send(&self, request: Request) -> BoxFuture<'_, Result<Reply, String>> {
+        async move {
+            let stream = tokio::net::UnixStream::connect(&self.path)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            let (mut sender, connection) = hyper::client::conn::handshake(stream)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            tokio::spawn(connection);
+
+            let mut builder =
+                hyper::Request::post(request.path).header(hyper::header::HOST, "localhost");
+
+            for (name, value) in request.headers.iter() {
+                builder = builder.header(name, value);
+            }
+
+            let req = builder
+                .body(hyper::Body::from(request.body))
+                .map_err(|e| e.to_string())?;
+
+            let resp = sender.send_request(req).await.map_err(|e| e.to_string())?;
+
+            let headers = resp
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    value
+                        .to_str()
+                        .ok()
+                        .map(|v| (name.to_string(), v.to_string()))
+                })
+                .collect();
+
+            Ok(Reply {
+                status: resp.status().as_u16(),
+                headers,
+                body: resp
+                    .into_body()
+                    .map(|chunk| chunk.map(|b| b.to_vec()).map_err(|e| e.to_string()))
+                    .boxed(),
+            })
+        }
+        .boxed()
+    }
+}
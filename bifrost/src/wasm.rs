@@ -0,0 +1,87 @@
+use heimdall::capability::Capability;
+use heimdall::registry::Registry;
+use heimdall::store::memory::MemoryStore;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// A compiled guest module, the `wasm32-wasi` executable of an application, executed within the
+/// current process by a dispatcher created with `Dispatcher::from_wasm`.
+///
+/// Modules are linked and executed by heimdall's runtime, exactly as they would be once uploaded
+/// to a heimdall instance along with their environment variables and capabilities.
+pub struct WasmModule {
+    id: String,
+    binary: Vec<u8>,
+    variables: Vec<(String, String)>,
+    capabilities: HashMap<String, HashMap<String, String>>,
+}
+
+impl WasmModule {
+    /// Loads the module compiled at `path`, such as
+    /// `target/wasm32-wasi/debug/my-app-remote.wasm`, under the id `module_id`.
+    pub fn load<P>(module_id: &str, path: P) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::from_binary(module_id, std::fs::read(path)?))
+    }
+
+    pub fn from_binary(module_id: &str, binary: Vec<u8>) -> Self {
+        WasmModule {
+            id: module_id.to_string(),
+            binary,
+            variables: Vec::new(),
+            capabilities: HashMap::new(),
+        }
+    }
+
+    /// Sets an environment variable of the module, as heimdall's `/:module_id/env` endpoint does.
+    pub fn with_variable(mut self, name: &str, value: &str) -> Self {
+        self.variables.retain(|(n, _)| n != name);
+        self.variables.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Grants a capability to the module, as heimdall's `/:module_id/caps` endpoint does, such as
+    /// `mongo` with its `connection_string` and `database` arguments.
+    pub fn with_capability(mut self, name: &str, args: &[(&str, &str)]) -> Self {
+        let args = args
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+
+        self.capabilities.insert(name.to_string(), args);
+        self
+    }
+}
+
+/// Builds a registry holding `modules`, checking that they compile and that their capabilities
+/// are valid so that errors are reported upfront rather than on the first request.
+pub(crate) fn registry(modules: Vec<WasmModule>) -> Result<Registry, String> {
+    let registry = Registry::new(Box::new(MemoryStore::new()), modules.len() as u64);
+
+    for module in modules {
+        for (cap, args) in module.capabilities.iter() {
+            Capability::from_config(cap, args).map_err(|e| {
+                format!(
+                    "invalid capability {} of module {}: {:?}",
+                    cap, module.id, e
+                )
+            })?;
+        }
+
+        if !registry.add(&module.id, module.binary) {
+            return Err(format!("unable to add module {}", module.id));
+        }
+
+        registry.attach_variables(&module.id, &module.variables);
+        registry.attach_capabilities(&module.id, &module.capabilities);
+
+        if registry.resolve(&module.id).is_none() {
+            return Err(format!("unable to compile module {}", module.id));
+        }
+    }
+
+    Ok(registry)
+}
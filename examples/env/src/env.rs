@@ -1,23 +1,18 @@
 use bifrost::op::Op;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-#[cfg(feature = "remote")]
-use serde::de::DeserializeOwned;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 struct GetEnvVar {
     var: String,
 }
 
+#[bifrost::op]
 impl Op for GetEnvVar {
     type Output = Option<String>;
 
-    fn id() -> &'static str {
-        "get_env_var"
-    }
-
-    #[cfg(any(feature = "remote", feature = "debug"))]
-    fn execute(&self) -> Self::Output {
-        std::env::var(&self.var).ok()
+    fn execute(self) -> Result<Self::Output, Self::Error> {
+        Ok(std::env::var(self.var).ok())
     }
 }
 
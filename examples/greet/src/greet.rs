@@ -1,51 +1,44 @@
-use bifrost::op::Op;
-use serde::{Deserialize, Serialize};
-#[cfg(feature = "remote")]
-use serde::de::DeserializeOwned;
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Greet {
-    name: String,
+#[bifrost::op(idempotent, cache_ttl = 60)]
+fn greet(name: String) -> String {
+    format!("Hi there, {}", name)
 }
 
-impl Op for Greet {
-    type Output = String;
-
-    fn id() -> &'static str {
-        "greet"
-    }
-
-    #[cfg(any(feature = "remote", feature = "debug"))]
-    fn execute(&self) -> Self::Output {
-        format!("Hi there, {}", self.name)
-    }
+#[bifrost::op(idempotent)]
+fn add_one(i: i32) -> i32 {
+    i + 1
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct AddOne {
-    i: i32,
+#[derive(Debug, serde::Deserialize, serde::Serialize, schemars::JsonSchema)]
+pub struct Countdown {
+    from: u32,
 }
 
-impl Op for AddOne {
-    type Output = i32;
+#[bifrost::op(idempotent)]
+impl bifrost::op::StreamingOp for Countdown {
+    type Item = u32;
 
-    fn id() -> &'static str {
-        "add_one"
-    }
+    fn execute(&self, items: &mut bifrost::op::Emitter<u32>) -> Result<(), Self::Error> {
+        for i in (0..=self.from).rev() {
+            items.emit(i);
+        }
 
-    #[cfg(any(feature = "remote", feature = "debug"))]
-    fn execute(&self) -> Self::Output {
-        self.i + 1
+        Ok(())
     }
 }
 
 #[cfg(any(feature = "local", feature = "debug"))]
 #[tokio::main]
 async fn main() {
+    use bifrost::cache::MemoryStore;
     use bifrost::dispatcher::Dispatcher;
+    use bifrost::retry::RetryPolicy;
+    use std::time::Duration;
 
-    let heimdall_execute_url = String::from("http://localhost:8080/greet-example/execute");
-    let dispatcher = Dispatcher::create(heimdall_execute_url);
+    let heimdall_url = String::from("http://localhost:8080");
+    let dispatcher = Dispatcher::for_module(heimdall_url, "greet-example")
+        .with_retry(RetryPolicy::default())
+        .with_timeout(Duration::from_secs(10))
+        .with_cache(MemoryStore::new());
 
     println!("Greet:");
     let op = Greet {
@@ -58,7 +51,29 @@ async fn main() {
     let op = AddOne { i: 41 };
     let result = dispatcher.send(&op).await;
     println!("Got result: {:?}", result);
+
+    println!("Batch:");
+    let mut batch = dispatcher.batch();
+    let greeting = batch.add(&Greet {
+        name: String::from("Heimdall"),
+    });
+    let sum = batch.add(&AddOne { i: 1 });
+    let results = dispatcher.send_batch(&batch).await;
+    println!("Got results: {:?}, {:?}", results.get(greeting), results.get(sum));
+
+    println!("Pipeline:");
+    let pipeline = dispatcher.pipeline(&AddOne { i: 1 });
+    let first = pipeline.step();
+    let pipeline = pipeline.then(&AddOne { i: 0 }).bind("/i", first, "");
+    let result = dispatcher.send_pipeline(&pipeline).await;
+    println!("Got result: {:?}", result);
+
+    println!("Stream:");
+    let mut items = dispatcher.send_stream(&Countdown { from: 3 }).await;
+    while let Some(item) = futures::StreamExt::next(&mut items).await {
+        println!("Got item: {:?}", item);
+    }
 }
 
 #[cfg(feature = "remote")]
-bifrost::entrypoint!(Greet, AddOne);
+bifrost::entrypoint!(Greet, AddOne; stream Countdown);
@@ -1,22 +1,29 @@
 use bifrost::op::Op;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-#[cfg(feature = "remote")]
-use serde::de::DeserializeOwned;
+use std::collections::HashMap;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
 struct Query {
     i: i32,
 }
 
+/// Ids of the inserted documents, and the documents found. BSON values are described as the JSON
+/// values they are serialized as.
+#[derive(Debug, Deserialize, Serialize, JsonSchema)]
+struct Queried(
+    #[schemars(with = "HashMap<u32, serde_json::Value>")] HashMap<u32, bson::Bson>,
+    #[schemars(with = "Vec<serde_json::Value>")] Vec<bson::Document>,
+);
+
 impl Op for Query {
-    type Output = Result<(std::collections::HashMap<u32, bson::Bson>, Vec<bson::Document>), u32>;
+    type Output = Queried;
+    type Error = u32;
 
-    fn id() -> &'static str {
-        "query"
-    }
+    const ID: &'static str = "query";
 
     #[cfg(any(feature = "remote"))]
-    fn execute(&self) -> Self::Output {
+    fn execute(self) -> Result<Self::Output, Self::Error> {
         let del = bson::Document::new();
         bifrost_mongodb::delete("test", del)?;
 
@@ -40,7 +47,7 @@ impl Op for Query {
         let doc = bson::Document::new();
         let find_res = bifrost_mongodb::find("test", &doc)?;
 
-        Ok((insert_res, find_res))
+        Ok(Queried(insert_res, find_res))
     }
 }
 
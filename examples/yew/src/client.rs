@@ -50,6 +50,7 @@ fn inc(dispatcher: Arc<Dispatcher>, state: UseStateHandle<State>) {
                     }
                 )
             },
+            Response::OpError(e) => match e {},
             Response::NetworkError(e) => {
                 state.set(
                     State {
@@ -73,7 +74,24 @@ fn inc(dispatcher: Arc<Dispatcher>, state: UseStateHandle<State>) {
                         error: Some(format!("Parse error: {}", e))
                     }
                 )
-            }
+            },
+            Response::Timeout => {
+                state.set(
+                    State {
+                        value: Increment { i: state.value.i },
+                        error: Some(String::from("Timed out"))
+                    }
+                )
+            },
+            Response::CircuitOpen => {
+                state.set(
+                    State {
+                        value: Increment { i: state.value.i },
+                        error: Some(String::from("Service unavailable, try again later"))
+                    }
+                )
+            },
+            Response::Cancelled => ()
         }
     });
 }
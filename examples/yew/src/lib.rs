@@ -1,23 +1,23 @@
 #[cfg(feature = "local")]
 pub mod client;
 
-use bifrost::op::Op;
+use bifrost::op::{Never, Op};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, JsonSchema)]
 pub struct Increment {
     i: i64
 }
 
 impl Op for Increment {
     type Output = i64;
+    type Error = Never;
 
-    fn id() -> &'static str {
-      "increment"
-    }
+    const ID: &'static str = "increment";
 
     #[cfg(feature = "remote")]
-    fn execute(&self) -> Self::Output {
-        self.i + 1
+    fn execute(self) -> Result<Self::Output, Self::Error> {
+        Ok(self.i + 1)
     }
 }
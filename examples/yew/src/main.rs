@@ -4,13 +4,6 @@ use bifrost_example_yew::Increment;
 #[cfg(feature = "local")]
 use bifrost_example_yew::client::App;
 
-#[cfg(feature = "remote")]
-use bifrost::op::Op;
-#[cfg(feature = "remote")]
-use serde::Serialize;
-#[cfg(feature = "remote")]
-use serde::de::DeserializeOwned;
-
 #[cfg(feature = "local")]
 fn main() {
   yew::start_app::<App>();
@@ -1,5 +1,6 @@
 use axum::extract::Extension;
 use axum::handler::Handler;
+use axum::middleware;
 use axum::routing;
 use axum::Router;
 use axum::Server;
@@ -7,9 +8,11 @@ use clap::Parser;
 use heimdall::handlers;
 use heimdall::registry::Registry;
 use heimdall::store::disk::DiskStore;
+use hyper::server::accept;
 use std::net::{IpAddr, Ipv6Addr, SocketAddr};
 use std::str::FromStr;
 use std::sync::Arc;
+use tokio::net::UnixListener;
 use tower::ServiceBuilder;
 use tower_http::auth::RequireAuthorizationLayer;
 use tower_http::cors::CorsLayer;
@@ -38,7 +41,7 @@ async fn main() {
     let handler_attach_capabilities = (handlers::attach_capabilities).layer(&auth_layer);
     let handler_delete = (handlers::delete).layer(&auth_layer);
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/:module_id/register", routing::post(handler_register))
         .route("/:module_id/env", routing::post(handler_attach_variables))
         .route(
@@ -46,20 +49,40 @@ async fn main() {
             routing::post(handler_attach_capabilities),
         )
         .route("/:module_id/delete", routing::delete(handler_delete))
-        .route("/:module_id/execute", routing::post(handlers::recv))
+        .route("/:module_id/manifest", routing::get(handlers::manifest))
+        .merge(handlers::execution_routes())
         .layer(Extension(Arc::new(registry)))
         .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()))
         .layer(CorsLayer::permissive());
 
+    if !args.trust_caller {
+        app = app.layer(middleware::from_fn(handlers::strip_caller));
+    }
+
     let sock_addr = SocketAddr::from((
         IpAddr::from_str(args.addr.as_str()).unwrap_or(IpAddr::V6(Ipv6Addr::LOCALHOST)),
         args.port,
     ));
 
-    Server::bind(&sock_addr)
-        .serve(app.into_make_service())
-        .await
-        .expect("Unable to start server");
+    let tcp = Server::bind(&sock_addr).serve(app.clone().into_make_service());
+
+    match args.socket {
+        None => tcp.await.expect("Unable to start server"),
+        Some(path) => {
+            let _ = std::fs::remove_file(&path);
+            let listener = UnixListener::bind(&path).expect("Unable to bind socket");
+            let incoming = accept::from_stream(futures::stream::poll_fn(move |cx| {
+                listener
+                    .poll_accept(cx)
+                    .map(|r| Some(r.map(|(stream, _)| stream)))
+            }));
+            let unix = Server::builder(incoming).serve(app.into_make_service());
+
+            let (tcp, unix) = tokio::join!(tcp, unix);
+            tcp.expect("Unable to start server");
+            unix.expect("Unable to start socket server");
+        }
+    }
 }
 
 #[derive(Parser)]
@@ -85,6 +108,15 @@ pub struct Args {
     #[arg(long = "dir")]
     pub module_dir: String,
 
+    /// Unix domain socket to listen on, in addition to the port
+    #[arg(long = "socket")]
+    pub socket: Option<String>,
+
+    /// Keep the x-bifrost-caller header of requests, for instances only reachable through a proxy
+    /// that authenticates callers and sets it
+    #[arg(long = "trust-caller")]
+    pub trust_caller: bool,
+
     /// Log level
     #[arg(long = "log", default_value = "debug")]
     log_level: String,
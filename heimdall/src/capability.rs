@@ -3,6 +3,7 @@ use std::collections::HashMap;
 use wasmtime::Linker;
 
 pub enum Capability {
+    #[cfg(feature = "mongodb")]
     MongoDB(MongoDB),
 }
 
@@ -13,11 +14,13 @@ pub enum CapabilityInitError {
 }
 
 impl Capability {
+    #[cfg_attr(not(feature = "mongodb"), allow(unused_variables))]
     pub fn from_config(
         cap: &str,
         args: &HashMap<String, String>,
     ) -> Result<Self, CapabilityInitError> {
         match cap {
+            #[cfg(feature = "mongodb")]
             "mongo" => {
                 let mdb = MongoDB::from_args(args)?;
                 Ok(Self::MongoDB(mdb))
@@ -26,18 +29,22 @@ impl Capability {
         }
     }
 
+    #[cfg_attr(not(feature = "mongodb"), allow(unused_variables))]
     pub fn add_to_linker<T: std::marker::Send>(&self, linker: &mut Linker<T>) -> Result<(), Error> {
-        match self {
-            Self::MongoDB(mdb) => mdb.add_to_linker(linker),
+        match *self {
+            #[cfg(feature = "mongodb")]
+            Self::MongoDB(ref mdb) => mdb.add_to_linker(linker),
         }
     }
 }
 
+#[cfg(feature = "mongodb")]
 pub struct MongoDB {
     connection_string: String,
     database: String,
 }
 
+#[cfg(feature = "mongodb")]
 impl MongoDB {
     pub const NAME: &'static str = "mongo";
     pub const CONNECTION_STRING: &'static str = "connection_string";
@@ -0,0 +1,58 @@
+use axum::http::HeaderMap;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Request header naming the op carried in the request body.
+pub const OP_HEADER: &str = "x-bifrost-op";
+
+/// Request header carrying the identity of the caller, as asserted by a trusted proxy in front of
+/// heimdall. Clients can set it too, so it is stripped from requests unless heimdall is started
+/// with `--trust-caller`, see `handlers::strip_caller`.
+pub const CALLER_HEADER: &str = "x-bifrost-caller";
+
+/// Request header carrying the request deadline, in milliseconds since the unix epoch.
+pub const DEADLINE_HEADER: &str = "x-bifrost-deadline";
+
+/// Request header carrying the request id.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Environment variable through which the context is passed to the guest.
+pub const CONTEXT_VAR: &str = "BIFROST_CONTEXT";
+
+/// Headers that are never forwarded to the guest.
+const PRIVATE_HEADERS: [&str; 2] = ["authorization", "cookie"];
+
+/// Request metadata made available to ops, mirroring `bifrost::Context`.
+#[derive(Debug, Serialize)]
+pub struct Context {
+    pub request_id: String,
+    /// Value of `CALLER_HEADER`, only set if the header was left in by a trusted proxy.
+    pub caller: Option<String>,
+    pub deadline: Option<u64>,
+    pub headers: HashMap<String, String>,
+}
+
+impl Context {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let headers: HashMap<String, String> = headers
+            .iter()
+            .filter(|(name, _)| !PRIVATE_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.to_string(), value.to_string()))
+            })
+            .collect();
+
+        Context {
+            request_id: headers
+                .get(REQUEST_ID_HEADER)
+                .cloned()
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            caller: headers.get(CALLER_HEADER).cloned(),
+            deadline: headers.get(DEADLINE_HEADER).and_then(|d| d.parse().ok()),
+            headers,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::HeaderMap;
+
+/// Content types understood by `bifrost` guests, see `bifrost::format::Format`.
+pub const FORMATS: [&str; 4] = [
+    "application/json",
+    "application/msgpack",
+    "application/cbor",
+    "application/x-bincode",
+];
+
+/// Format assumed for requests without a `Content-Type`.
+pub const DEFAULT_FORMAT: &str = "application/json";
+
+/// Serialization formats of an op payload and of its result.
+#[derive(Clone, Copy, Debug)]
+pub struct Formats {
+    pub input: &'static str,
+    pub output: &'static str,
+}
+
+impl Formats {
+    /// Resolves the payload format from `Content-Type`, and the result format from `Accept`,
+    /// falling back to the payload format. Returns the offending content type if the payload
+    /// format is not supported.
+    pub fn negotiate(headers: &HeaderMap) -> Result<Self, String> {
+        let input = match headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) {
+            None => DEFAULT_FORMAT,
+            Some(content_type) => resolve(content_type).ok_or_else(|| content_type.to_string())?,
+        };
+
+        let output = headers
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|accept| accept.split(',').find_map(resolve))
+            .unwrap_or(input);
+
+        Ok(Formats { input, output })
+    }
+}
+
+fn resolve(media_type: &str) -> Option<&'static str> {
+    let media_type = media_type.split(';').next().unwrap_or("").trim();
+
+    FORMATS
+        .into_iter()
+        .find(|f| f.eq_ignore_ascii_case(media_type))
+}
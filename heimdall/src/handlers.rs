@@ -1,11 +1,32 @@
+use crate::context::{Context, CALLER_HEADER, OP_HEADER};
+use crate::format::Formats;
 use crate::registry::Registry;
 use crate::runtime;
+use axum::body::Bytes;
 use axum::extract::{Extension, Json, Multipart, Path};
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{routing, Router};
 use log::{debug, error};
 use std::collections::HashMap;
 use std::sync::Arc;
 
+/// Routes executing ops, which expect an `Extension<Arc<Registry>>` layer.
+pub fn execution_routes() -> Router {
+    Router::new()
+        .route("/:module_id/execute", routing::post(recv))
+        .route("/:module_id/batch", routing::post(recv_batch))
+        .route("/:module_id/pipeline", routing::post(recv_pipeline))
+        .route("/:module_id/stream", routing::post(recv_stream))
+}
+
+/// Middleware removing the caller identity sent by clients, which only a trusted proxy may set.
+pub async fn strip_caller<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    req.headers_mut().remove(CALLER_HEADER);
+    next.run(req).await
+}
+
 pub async fn register(
     Path(module_id): Path<String>,
     mut multipart: Multipart,
@@ -85,15 +106,127 @@ pub async fn delete(
     }
 }
 
+/// Serves the manifest of a module, listing the ops it executes.
+pub async fn manifest(
+    Path(module_id): Path<String>,
+    Extension(registry): Extension<Arc<Registry>>,
+) -> Response {
+    debug!("serving manifest of module {}", module_id);
+
+    match registry.manifest(module_id.as_str()).await {
+        Some(manifest) => Json(manifest).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
 pub async fn recv(
     Path(module_id): Path<String>,
-    Json((label, json)): Json<(String, serde_json::Value)>,
+    headers: HeaderMap,
+    payload: Bytes,
+    Extension(registry): Extension<Arc<Registry>>,
+) -> runtime::ExecutionResult {
+    let label = match headers.get(OP_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(label) => label,
+        None => {
+            error!("missing {} header on request for {}", OP_HEADER, module_id);
+            return runtime::ExecutionResult::MissingOp;
+        }
+    };
+
+    exec(&registry, &module_id, label, &headers, payload).await
+}
+
+pub async fn recv_batch(
+    Path(module_id): Path<String>,
+    headers: HeaderMap,
+    payload: Bytes,
+    Extension(registry): Extension<Arc<Registry>>,
+) -> runtime::ExecutionResult {
+    exec(
+        &registry,
+        &module_id,
+        runtime::BATCH_LABEL,
+        &headers,
+        payload,
+    )
+    .await
+}
+
+pub async fn recv_pipeline(
+    Path(module_id): Path<String>,
+    headers: HeaderMap,
+    payload: Bytes,
     Extension(registry): Extension<Arc<Registry>>,
 ) -> runtime::ExecutionResult {
+    exec(
+        &registry,
+        &module_id,
+        runtime::PIPELINE_LABEL,
+        &headers,
+        payload,
+    )
+    .await
+}
+
+pub async fn recv_stream(
+    Path(module_id): Path<String>,
+    headers: HeaderMap,
+    payload: Bytes,
+    Extension(registry): Extension<Arc<Registry>>,
+) -> Response {
+    let context = Context::from_headers(&headers);
+
+    let label = match headers.get(OP_HEADER).and_then(|v| v.to_str().ok()) {
+        Some(label) => label,
+        None => {
+            error!("missing {} header on request for {}", OP_HEADER, module_id);
+            return runtime::ExecutionResult::MissingOp.into_response();
+        }
+    };
+
+    let formats = match Formats::negotiate(&headers) {
+        Ok(formats) => formats,
+        Err(content_type) => {
+            error!("unsupported content type on request {}", context.request_id);
+            return runtime::ExecutionResult::UnsupportedFormat(content_type).into_response();
+        }
+    };
+
+    debug!(
+        "processing streaming request {} for {}: ({}, {} bytes)",
+        context.request_id,
+        module_id,
+        label,
+        payload.len()
+    );
+
+    runtime::stream(&registry, &module_id, label, payload, formats, context).await
+}
+
+async fn exec(
+    registry: &Registry,
+    module_id: &str,
+    label: &str,
+    headers: &HeaderMap,
+    payload: Bytes,
+) -> runtime::ExecutionResult {
+    let context = Context::from_headers(headers);
+
+    let formats = match Formats::negotiate(headers) {
+        Ok(formats) => formats,
+        Err(content_type) => {
+            error!("unsupported content type on request {}", context.request_id);
+            return runtime::ExecutionResult::UnsupportedFormat(content_type);
+        }
+    };
+
     debug!(
-        "processing request for {}: ({}, {:?})",
-        module_id, label, json
+        "processing request {} for {}: ({}, {} bytes)",
+        context.request_id,
+        module_id,
+        label,
+        payload.len()
     );
 
-    runtime::exec(&registry, &module_id, label.as_str(), &json).await
+    runtime::exec(registry, module_id, label, payload, formats, &context).await
 }
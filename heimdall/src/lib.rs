@@ -1,5 +1,8 @@
 pub mod capability;
+pub mod context;
+pub mod format;
 pub mod handlers;
+pub mod manifest;
 pub mod registry;
 pub mod runtime;
 pub mod store;
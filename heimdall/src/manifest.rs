@@ -0,0 +1,272 @@
+use serde_json::{json, Value};
+
+/// Name of the custom section in which `bifrost::entrypoint!` embeds the manifest of a guest.
+pub const SECTION: &str = "bifrost_manifest";
+
+const MAGIC: &[u8] = b"\0asm";
+
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// Reads the manifest of a guest module, listing the ops it executes as `{"ops": [...]}`.
+///
+/// The manifest section holds one JSON object per op and per line, see `bifrost::op::Op::MANIFEST`.
+/// Modules built without a manifest yield `None`.
+pub fn parse(binary: &[u8]) -> Result<Option<Value>, String> {
+    if binary.len() < 8 || &binary[..4] != MAGIC {
+        return Err("not a WebAssembly module".to_string());
+    }
+
+    let mut entries = Vec::new();
+    let mut found = false;
+    let mut pos = 8;
+
+    while pos < binary.len() {
+        let id = binary[pos];
+        pos += 1;
+
+        let size = leb128(binary, &mut pos)? as usize;
+        let end = pos
+            .checked_add(size)
+            .filter(|end| *end <= binary.len())
+            .ok_or("truncated section")?;
+
+        if id == CUSTOM_SECTION_ID {
+            let name_len = leb128(binary, &mut pos)? as usize;
+            let name = binary
+                .get(pos..pos + name_len)
+                .filter(|_| pos + name_len <= end)
+                .ok_or("truncated section name")?;
+
+            if name == SECTION.as_bytes() {
+                found = true;
+                entries.extend(lines(&binary[pos + name_len..end])?);
+            }
+        }
+
+        pos = end;
+    }
+
+    Ok(found.then(|| json!({ "ops": entries })))
+}
+
+/// Whether the ops of a manifest were described by `describe`.
+pub fn described(manifest: &Value) -> bool {
+    entries(manifest).all(|entry| entry.get("schemas").is_some())
+}
+
+/// Adds to each op of a manifest the schemas of its types, as `"schemas"`, from those reported by
+/// the guest keyed on op id. Ops the guest does not describe get `null` schemas, which tells them
+/// apart from manifests not described yet.
+pub fn describe(manifest: &mut Value, schemas: &Value) {
+    if let Some(entries) = manifest.get_mut("ops").and_then(Value::as_array_mut) {
+        for entry in entries.iter_mut().filter_map(Value::as_object_mut) {
+            let op_schemas = entry
+                .get("id")
+                .and_then(Value::as_str)
+                .and_then(|id| schemas.get(id))
+                .cloned()
+                .unwrap_or(Value::Null);
+
+            entry.insert("schemas".to_string(), op_schemas);
+        }
+    }
+}
+
+fn entries(manifest: &Value) -> impl Iterator<Item = &Value> {
+    manifest
+        .get("ops")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+}
+
+/// Entries of a manifest section, as concatenated by the linker.
+fn lines(section: &[u8]) -> Result<Vec<Value>, String> {
+    section
+        .split(|b| *b == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            serde_json::from_slice(line).map_err(|e| format!("invalid manifest entry: {}", e))
+        })
+        .collect()
+}
+
+/// Reads an unsigned LEB128 number, as section sizes are encoded.
+fn leb128(binary: &[u8], pos: &mut usize) -> Result<u32, String> {
+    let mut value = 0u32;
+
+    for shift in (0..35).step_by(7) {
+        let byte = *binary.get(*pos).ok_or("truncated number")?;
+        *pos += 1;
+
+        value |= ((byte & 0x7f) as u32) << shift;
+
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+
+    Err("invalid number".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A module with the given sections, each as an id and its contents.
+    fn module(sections: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut binary = b"\0asm\x01\0\0\0".to_vec();
+
+        for (id, contents) in sections {
+            binary.push(*id);
+            binary.extend(encode(contents.len() as u32));
+            binary.extend(contents);
+        }
+
+        binary
+    }
+
+    fn custom(name: &str, contents: &str) -> (u8, Vec<u8>) {
+        let mut section = encode(name.len() as u32);
+        section.extend(name.as_bytes());
+        section.extend(contents.as_bytes());
+        (CUSTOM_SECTION_ID, section)
+    }
+
+    fn encode(mut value: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    #[test]
+    fn rejects_other_binaries() {
+        assert!(parse(b"not wasm").is_err());
+        assert!(parse(b"\0asm").is_err());
+    }
+
+    #[test]
+    fn modules_without_manifest() {
+        let binary = module(&[(1, vec![0]), custom("name", "guest")]);
+        assert_eq!(parse(&binary), Ok(None));
+    }
+
+    #[test]
+    fn concatenated_entries() {
+        let binary = module(&[
+            (1, vec![0]),
+            custom(SECTION, "{\"id\":\"greet\"}\n{\"id\":\"add_one\"}\n"),
+            custom("name", "guest"),
+            custom(SECTION, "{\"id\":\"countdown\"}\n"),
+        ]);
+
+        assert_eq!(
+            parse(&binary),
+            Ok(Some(json!({
+                "ops": [{ "id": "greet" }, { "id": "add_one" }, { "id": "countdown" }]
+            })))
+        );
+    }
+
+    #[test]
+    fn sections_larger_than_a_byte() {
+        let description = "a".repeat(300);
+        let entry = format!("{{\"id\":\"greet\",\"description\":\"{}\"}}\n", description);
+        let binary = module(&[custom(SECTION, &entry)]);
+
+        assert_eq!(
+            parse(&binary),
+            Ok(Some(json!({
+                "ops": [{ "id": "greet", "description": description }]
+            })))
+        );
+    }
+
+    #[test]
+    fn invalid_entries() {
+        let binary = module(&[custom(SECTION, "{\"id\":\"greet\"}\nnot json\n")]);
+        assert!(parse(&binary).is_err());
+    }
+
+    #[test]
+    fn truncated_sections() {
+        let binary = module(&[custom(SECTION, "{\"id\":\"greet\"}\n")]);
+
+        for len in 9..binary.len() {
+            assert!(parse(&binary[..len]).is_err(), "truncated to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn truncated_section_names() {
+        // The name claims 16 bytes, more than the 4 bytes of the section.
+        let binary = module(&[(CUSTOM_SECTION_ID, vec![16, b'n', b'a', b'm'])]);
+        assert!(parse(&binary).is_err());
+    }
+
+    #[test]
+    fn described_entries() {
+        let mut manifest = json!({ "ops": [{ "id": "greet" }, { "id": "countdown" }] });
+        assert!(!described(&manifest));
+
+        let greet = json!({ "input": { "type": "object" }, "output": { "type": "string" } });
+        describe(&mut manifest, &json!({ "greet": greet, "other": {} }));
+
+        assert!(described(&manifest));
+        assert_eq!(
+            manifest,
+            json!({
+                "ops": [
+                    { "id": "greet", "schemas": greet },
+                    { "id": "countdown", "schemas": null }
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn guests_describing_no_ops() {
+        let mut manifest = json!({ "ops": [{ "id": "greet" }] });
+        describe(&mut manifest, &json!({}));
+
+        assert!(described(&manifest));
+        assert_eq!(
+            manifest,
+            json!({ "ops": [{ "id": "greet", "schemas": null }] })
+        );
+    }
+
+    #[test]
+    fn leb128_numbers() {
+        for (bytes, expected) in [
+            (&[0x00][..], 0),
+            (&[0x7f], 127),
+            (&[0x80, 0x01], 128),
+            (&[0xe5, 0x8e, 0x26], 624_485),
+            (&[0xff, 0xff, 0xff, 0xff, 0x0f], u32::MAX),
+        ] {
+            let mut pos = 0;
+            assert_eq!(leb128(bytes, &mut pos), Ok(expected));
+            assert_eq!(pos, bytes.len());
+        }
+    }
+
+    #[test]
+    fn invalid_leb128_numbers() {
+        let mut pos = 0;
+        assert!(leb128(&[0x80, 0x80], &mut pos).is_err());
+
+        let mut pos = 0;
+        assert!(leb128(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x01], &mut pos).is_err());
+    }
+}
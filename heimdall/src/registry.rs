@@ -1,7 +1,10 @@
 use crate::capability::{Capability, CapabilityInitError};
+use crate::manifest;
+use crate::runtime;
 use crate::store::Store;
 use log::{debug, error};
 use moka::sync::Cache;
+use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
 use wasmtime::{Config, Engine, Module};
@@ -28,9 +31,56 @@ impl Registry {
         }
     }
 
+    /// Stores a module along with its manifest, if it has one. Binaries that are not WebAssembly
+    /// modules, or whose manifest is malformed, are rejected.
     pub fn add(&self, module_id: &str, binary: Vec<u8>) -> bool {
         debug!("adding module to registry: {}", module_id);
-        self.store.store(module_id, binary)
+
+        let manifest = match manifest::parse(&binary) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                error!("cannot add module {}: {}", module_id, e);
+                return false;
+            }
+        };
+
+        if !self.store.store(module_id, binary) {
+            return false;
+        }
+
+        match manifest {
+            Some(manifest) if !self.store.attach_manifest(module_id, &manifest) => {
+                // A module stored without its manifest would be served as if it had none.
+                error!("cannot attach manifest to module {}", module_id);
+                self.store.delete(module_id);
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Manifest of a module, listing the ops it executes, unless it was built without one.
+    ///
+    /// The schemas of the ops are asked of the guest on the first read rather than when the module
+    /// is added, as the guest may not run until its capabilities are attached. They are stored
+    /// with the manifest once known, and left out until then.
+    pub async fn manifest(&self, module_id: &str) -> Option<Value> {
+        debug!("retrieving manifest of module: {}", module_id);
+        let mut manifest = self.store.retrieve_manifest(module_id)?;
+
+        if manifest::described(&manifest) {
+            return Some(manifest);
+        }
+
+        if let Some(schemas) = runtime::schemas(self, module_id).await {
+            manifest::describe(&mut manifest, &schemas);
+
+            if !self.store.attach_manifest(module_id, &manifest) {
+                error!("cannot attach schemas to module {}", module_id);
+            }
+        }
+
+        Some(manifest)
     }
 
     pub fn attach_variables(&self, module_id: &str, variables: &Vec<(String, String)>) -> bool {
@@ -87,6 +137,7 @@ impl Registry {
 
         let mut config = Config::new();
         config.async_support(true);
+        config.consume_fuel(true);
         let engine = Engine::new(&config).ok()?;
 
         match Module::from_binary(&engine, &binary) {
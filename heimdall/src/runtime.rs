@@ -1,30 +1,247 @@
+use crate::context::{Context, CONTEXT_VAR, OP_HEADER};
+use crate::format::{Formats, DEFAULT_FORMAT};
 use crate::registry::Environment;
 use crate::registry::Registry;
-use axum::http::StatusCode;
+use axum::body::{Bytes, StreamBody};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::{IntoResponse, Response};
+use axum::Json;
 use log::{debug, error};
+use serde_json::{Map, Value};
+use std::any::Any;
+use std::convert::Infallible;
+use std::io::IoSlice;
 use std::string::ToString;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::{self, Sender};
+use wasi_common::file::FileType;
+use wasi_common::pipe::WritePipe;
+use wasi_common::WasiFile;
 use wasmtime::*;
 use wasmtime_wasi::tokio::WasiCtxBuilder;
 
+// Guest exit codes, see `bifrost::guest`. Apart from success, the guest writes either the
+// serialized op error or a diagnostic message to stderr.
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_OP_ERROR: i32 = 1;
+const EXIT_UNKNOWN_OP: i32 = 2;
+const EXIT_INPUT_DECODE_ERROR: i32 = 3;
+const EXIT_OUTPUT_ENCODE_ERROR: i32 = 4;
+const EXIT_PANIC: i32 = 5;
+
+/// Not a guest exit code: reported in place of one when the request deadline passes before the
+/// guest exits.
+const EXIT_DEADLINE_EXCEEDED: i32 = -1;
+
+/// Fuel given to a guest between two yields to the executor, which lets executions be interrupted
+/// once their deadline passes.
+const FUEL_PER_YIELD: u64 = 10_000;
+
+/// Writes of a streaming guest buffered while the client is not reading them, past which the
+/// guest waits for the client.
+const STREAM_BUFFER: usize = 16;
+
+/// Label under which the guest executes a batch of ops, see `bifrost::protocol`.
+pub const BATCH_LABEL: &str = "__bifrost_batch";
+
+/// Label under which the guest executes a pipeline of ops, see `bifrost::protocol`.
+pub const PIPELINE_LABEL: &str = "__bifrost_pipeline";
+
+/// Label under which the guest describes the types of its ops, see `bifrost::protocol`.
+pub const SCHEMAS_LABEL: &str = "__bifrost_schemas";
+
+/// Content type of streamed op outputs, one JSON frame per line, see `bifrost::protocol::Frame`.
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// Response header describing a failed execution to the dispatcher.
+const ERROR_HEADER: &str = "x-bifrost-error";
+
+/// `ERROR_HEADER` value for errors returned by the op itself.
+const OP_ERROR: &str = "op";
+
+/// `ERROR_HEADER` value for executions stopped because their deadline passed.
+const DEADLINE_EXCEEDED: &str = "deadline_exceeded";
+
+struct Exit {
+    code: i32,
+    stderr: Vec<u8>,
+}
+
+/// Forwards guest output to a streamed response as it is written, holding writes back while the
+/// response is not read.
+struct ChannelFile(Sender<Bytes>);
+
 pub async fn exec(
     registry: &Registry,
     module_id: &str,
     label: &str,
-    json: &serde_json::Value,
+    payload: Bytes,
+    formats: Formats,
+    context: &Context,
 ) -> ExecutionResult {
     debug!("executing request for module {}", module_id);
 
-    match registry.resolve(module_id) {
-        None => ExecutionResult::ModuleResolutionError,
-        Some(env_ref) => match exec_env(&*env_ref, label, json).await {
+    let env_ref = match registry.resolve(module_id) {
+        None => return ExecutionResult::ModuleResolutionError,
+        Some(env_ref) => env_ref,
+    };
+
+    let stdout = WritePipe::new_in_memory();
+
+    let exit = match exec_env(
+        &env_ref,
+        label,
+        payload,
+        formats,
+        context,
+        Box::new(stdout.clone()),
+    )
+    .await
+    {
+        None => return ExecutionResult::RuntimeExecutionError,
+        Some(exit) => exit,
+    };
+
+    match exit.code {
+        EXIT_SUCCESS => match read_pipe(stdout, "unable to retrieve stdout output") {
             None => ExecutionResult::RuntimeExecutionError,
-            Some(res) => ExecutionResult::Success(res),
+            Some(stdout) => ExecutionResult::Success(stdout, formats.output),
+        },
+        EXIT_OP_ERROR => ExecutionResult::OpError(exit.stderr, formats.output),
+        code => failure(code, exit.message()),
+    }
+}
+
+/// Asks a module for the JSON schemas of its ops, keyed on op id.
+///
+/// Guests built before schemas were introduced do not know the label, and describe none of their
+/// ops. Other failures, such as a capability of the module not being attached yet, yield `None`.
+pub async fn schemas(registry: &Registry, module_id: &str) -> Option<Value> {
+    let formats = Formats {
+        input: DEFAULT_FORMAT,
+        output: DEFAULT_FORMAT,
+    };
+    let context = Context::from_headers(&HeaderMap::new());
+    let payload = Bytes::new();
+
+    match exec(
+        registry,
+        module_id,
+        SCHEMAS_LABEL,
+        payload,
+        formats,
+        &context,
+    )
+    .await
+    {
+        ExecutionResult::Success(stdout, _) => match serde_json::from_slice(&stdout) {
+            Ok(schemas) => Some(schemas),
+            Err(e) => {
+                error!("invalid schemas from module {}: {}", module_id, e);
+                None
+            }
         },
+        ExecutionResult::UnknownOp(_) => Some(Value::Object(Map::new())),
+        _ => {
+            error!("unable to retrieve schemas of module {}", module_id);
+            None
+        }
+    }
+}
+
+/// Executes a streaming op, responding with its frames as the guest writes them.
+///
+/// Failures occurring once the response has started, other than op errors which the guest
+/// reports itself, are appended as a final `failure` frame. The guest is stopped if the client
+/// goes away before it exits.
+pub async fn stream(
+    registry: &Registry,
+    module_id: &str,
+    label: &str,
+    payload: Bytes,
+    formats: Formats,
+    context: Context,
+) -> Response {
+    debug!("streaming request for module {}", module_id);
+
+    let env_ref = match registry.resolve(module_id) {
+        None => return ExecutionResult::ModuleResolutionError.into_response(),
+        Some(env_ref) => env_ref,
+    };
+
+    let (sender, mut receiver) = mpsc::channel(STREAM_BUFFER);
+    let stdout = ChannelFile(sender.clone());
+    let label = label.to_string();
+
+    tokio::spawn(async move {
+        let execution = exec_env(
+            &env_ref,
+            &label,
+            payload,
+            formats,
+            &context,
+            Box::new(stdout),
+        );
+
+        let result = tokio::select! {
+            result = execution => result,
+            _ = sender.closed() => {
+                debug!("client went away during request {}", context.request_id);
+                return;
+            }
+        };
+
+        let error = match result {
+            None => ExecutionResult::RuntimeExecutionError,
+            Some(exit) => match exit.code {
+                EXIT_SUCCESS | EXIT_OP_ERROR => return,
+                code => failure(code, exit.message()),
+            },
+        };
+
+        if let Some(frame) = error.frame() {
+            let _ = sender.send(frame).await;
+        }
+    });
+
+    let frames = futures::stream::poll_fn(move |cx| {
+        receiver
+            .poll_recv(cx)
+            .map(|frame| frame.map(Ok::<_, Infallible>))
+    });
+
+    (
+        StatusCode::OK,
+        [(CONTENT_TYPE, NDJSON)],
+        StreamBody::new(frames),
+    )
+        .into_response()
+}
+
+/// Result of a guest that exited with a code other than success or an op error.
+fn failure(code: i32, message: String) -> ExecutionResult {
+    match code {
+        EXIT_UNKNOWN_OP => ExecutionResult::UnknownOp(message),
+        EXIT_INPUT_DECODE_ERROR => ExecutionResult::InputDecodeError(message),
+        EXIT_OUTPUT_ENCODE_ERROR => ExecutionResult::OutputEncodeError(message),
+        EXIT_PANIC => ExecutionResult::Panic(message),
+        EXIT_DEADLINE_EXCEEDED => ExecutionResult::DeadlineExceeded,
+        code => {
+            error!("guest exited with unexpected code {}", code);
+            ExecutionResult::RuntimeExecutionError
+        }
     }
 }
 
-async fn exec_env(env: &Environment, label: &str, json: &serde_json::Value) -> Option<String> {
+async fn exec_env(
+    env: &Environment,
+    label: &str,
+    payload: Bytes,
+    formats: Formats,
+    context: &Context,
+    stdout: Box<dyn WasiFile>,
+) -> Option<Exit> {
     let engine = &env.engine;
     let module = &env.module;
     let variables = &env.variables;
@@ -44,23 +261,33 @@ async fn exec_env(env: &Environment, label: &str, json: &serde_json::Value) -> O
         )?;
     }
 
-    let json = or_error(serde_json::to_string(json), "serializing runtime payload")?;
+    let context_json = or_error(
+        serde_json::to_string(context),
+        "serializing request context",
+    )?;
 
-    let stdout = wasi_common::pipe::WritePipe::new_in_memory();
+    let stdin = wasi_common::pipe::ReadPipe::new(std::io::Cursor::new(payload));
+    let stderr = WritePipe::new_in_memory();
 
-    {
+    let exit_code = {
         let wasi = or_error(
             WasiCtxBuilder::new()
-                .stdout(Box::new(stdout.clone()))
+                .stdin(Box::new(stdin))
+                .stdout(stdout)
+                .stderr(Box::new(stderr.clone()))
                 .arg(label)
-                .and_then(|b| b.arg(&json))
+                .and_then(|b| b.arg(formats.input))
+                .and_then(|b| b.arg(formats.output))
                 .and_then(|b| b.envs(variables))
+                .and_then(|b| b.env(CONTEXT_VAR, &context_json))
                 .map(|b| b.build()),
             "failed to build WASI context",
         )?;
 
         let mut store = Store::new(engine, wasi);
 
+        store.out_of_fuel_async_yield(u64::MAX, FUEL_PER_YIELD);
+
         or_error(
             linker.module_async(&mut store, "", module).await,
             "unable to link module",
@@ -73,26 +300,93 @@ async fn exec_env(env: &Environment, label: &str, json: &serde_json::Value) -> O
             "unable to resolve WASM entrypoint",
         )?;
 
-        or_error(
-            entrypoint.call_async(&mut store, ()).await,
-            "unable to execute WASM entrypoint",
-        )?;
-    }
+        let call = entrypoint.call_async(&mut store, ());
 
-    let stdout_contents: Vec<u8> = or_error(
-        stdout
-            .try_into_inner()
-            .map(|i| i.into_inner())
-            .map_err(|_| "pipe still referenced elsewhere"),
-        "unable to retrieve stdout output",
-    )?;
+        let result = match remaining(context) {
+            None => call.await,
+            Some(remaining) => match tokio::time::timeout(remaining, call).await {
+                Ok(result) => result,
+                Err(_) => {
+                    debug!("deadline exceeded on request {}", context.request_id);
+                    return Some(Exit {
+                        code: EXIT_DEADLINE_EXCEEDED,
+                        stderr: Vec::new(),
+                    });
+                }
+            },
+        };
 
+        match result {
+            Ok(()) => EXIT_SUCCESS,
+            Err(trap) => or_error(
+                trap.i32_exit_status().ok_or(trap),
+                "unable to execute WASM entrypoint",
+            )?,
+        }
+    };
+
+    Some(Exit {
+        code: exit_code,
+        stderr: read_pipe(stderr, "unable to retrieve stderr output")?,
+    })
+}
+
+/// Time left until the deadline of a request, if it has one.
+fn remaining(context: &Context) -> Option<Duration> {
+    let deadline = UNIX_EPOCH + Duration::from_millis(context.deadline?);
+
+    Some(
+        deadline
+            .duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+fn read_pipe(pipe: WritePipe<std::io::Cursor<Vec<u8>>>, prefix: &'static str) -> Option<Vec<u8>> {
     or_error(
-        std::str::from_utf8(&stdout_contents).map(String::from),
-        "unable to read stdout contents",
+        pipe.try_into_inner()
+            .map(|i| i.into_inner())
+            .map_err(|_| "pipe still referenced elsewhere"),
+        prefix,
     )
 }
 
+impl Exit {
+    /// Diagnostic message written to stderr by the guest.
+    fn message(&self) -> String {
+        String::from_utf8_lossy(&self.stderr).into_owned()
+    }
+}
+
+#[async_trait::async_trait]
+impl WasiFile for ChannelFile {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    async fn get_filetype(&mut self) -> Result<FileType, wasi_common::Error> {
+        Ok(FileType::Pipe)
+    }
+
+    async fn write_vectored<'a>(
+        &mut self,
+        bufs: &[IoSlice<'a>],
+    ) -> Result<u64, wasi_common::Error> {
+        let bytes: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        let len = bytes.len() as u64;
+
+        self.0.send(Bytes::from(bytes)).await.map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::BrokenPipe, "client disconnected")
+        })?;
+
+        Ok(len)
+    }
+
+    async fn writable(&self) -> Result<(), wasi_common::Error> {
+        Ok(())
+    }
+}
+
 #[inline]
 fn or_error<T, E>(res: Result<T, E>, prefix: &'static str) -> Option<T>
 where
@@ -108,21 +402,107 @@ where
 }
 
 pub enum ExecutionResult {
-    Success(String),
+    Success(Vec<u8>, &'static str),
+    OpError(Vec<u8>, &'static str),
+    UnknownOp(String),
+    InputDecodeError(String),
+    OutputEncodeError(String),
+    Panic(String),
+    MissingOp,
+    UnsupportedFormat(String),
     ModuleResolutionError,
     RuntimeExecutionError,
+    DeadlineExceeded,
+}
+
+impl ExecutionResult {
+    /// Status, kind and message describing results other than successes and op errors.
+    fn error(self) -> Option<(StatusCode, &'static str, String)> {
+        let error = match self {
+            Self::Success(..) | Self::OpError(..) => return None,
+            Self::UnknownOp(message) => (StatusCode::NOT_FOUND, "unknown_op", message),
+            Self::InputDecodeError(message) => (StatusCode::BAD_REQUEST, "input_decode", message),
+            Self::OutputEncodeError(message) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, "output_encode", message)
+            }
+            Self::Panic(message) => (StatusCode::INTERNAL_SERVER_ERROR, "panic", message),
+            Self::MissingOp => (
+                StatusCode::BAD_REQUEST,
+                "missing_op",
+                format!("Missing {} header", OP_HEADER),
+            ),
+            Self::UnsupportedFormat(content_type) => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_format",
+                format!("Unsupported content type: {}", content_type),
+            ),
+            Self::ModuleResolutionError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "module_resolution",
+                "Module resolution error".to_string(),
+            ),
+            Self::RuntimeExecutionError => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "runtime_execution",
+                "Runtime execution error".to_string(),
+            ),
+            Self::DeadlineExceeded => (
+                StatusCode::GATEWAY_TIMEOUT,
+                DEADLINE_EXCEEDED,
+                "Deadline exceeded".to_string(),
+            ),
+        };
+
+        Some(error)
+    }
+
+    /// The error as the final frame of a streamed response.
+    fn frame(self) -> Option<Bytes> {
+        let (status, kind, message) = self.error()?;
+
+        let frame = serde_json::json!({
+            "failure": {
+                "status": status.as_u16(),
+                "kind": kind,
+                "message": message,
+            }
+        });
+
+        let mut line = frame.to_string();
+        line.push('\n');
+
+        Some(Bytes::from(line))
+    }
 }
 
 impl IntoResponse for ExecutionResult {
     fn into_response(self) -> Response {
         match self {
-            Self::Success(json) => (StatusCode::OK, json).into_response(),
-            Self::ModuleResolutionError => {
-                (StatusCode::INTERNAL_SERVER_ERROR, "Module resolution error").into_response()
-            }
-            Self::RuntimeExecutionError => {
-                (StatusCode::INTERNAL_SERVER_ERROR, "Runtime execution error").into_response()
+            Self::Success(output, content_type) => {
+                (StatusCode::OK, [(CONTENT_TYPE, content_type)], output).into_response()
             }
+            Self::OpError(error, content_type) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                [
+                    (ERROR_HEADER, OP_ERROR),
+                    (CONTENT_TYPE.as_str(), content_type),
+                ],
+                error,
+            )
+                .into_response(),
+            other => match other.error() {
+                Some((status, kind, message)) => error_response(status, kind, message),
+                None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            },
         }
     }
 }
+
+fn error_response(status: StatusCode, kind: &'static str, message: String) -> Response {
+    let body = serde_json::json!({
+        "error": kind,
+        "message": message,
+    });
+
+    (status, [(ERROR_HEADER, kind)], Json(body)).into_response()
+}
@@ -1,5 +1,7 @@
 pub mod disk;
+pub mod memory;
 
+use serde_json::Value;
 use std::collections::HashMap;
 
 pub trait Store {
@@ -13,6 +15,9 @@ pub trait Store {
         capabilities: &HashMap<String, HashMap<String, String>>,
     ) -> bool;
 
+    /// Keeps the manifest read from a module on registration, see `crate::manifest`.
+    fn attach_manifest(&self, module_id: &str, manifest: &Value) -> bool;
+
     fn delete(&self, module_id: &str) -> bool;
 
     fn retrieve(
@@ -23,4 +28,6 @@ pub trait Store {
         Vec<(String, String)>,
         HashMap<String, HashMap<String, String>>,
     )>;
+
+    fn retrieve_manifest(&self, module_id: &str) -> Option<Value>;
 }
@@ -1,5 +1,6 @@
 use crate::store::Store;
 use log::{debug, error, warn};
+use serde_json::Value;
 use std::collections::HashMap;
 use std::string::ToString;
 
@@ -100,6 +101,33 @@ impl Store for DiskStore {
         }
     }
 
+    fn attach_manifest(&self, module_id: &str, manifest: &Value) -> bool {
+        let path = std::path::Path::new(&self.dir).join(module_id);
+        debug!("attaching manifest to module at {:?}", path);
+
+        if !path.exists() {
+            error!("cannot attach manifest to missing module at {:?}", &path);
+            return false;
+        }
+
+        let manifest_path = path.join("manifest.json");
+
+        let result = serde_json::to_string(manifest)
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(&manifest_path, json).map_err(|e| e.to_string()));
+
+        match result {
+            Ok(_) => true,
+            Err(e) => {
+                error!(
+                    "failed to attach manifest to module at {:?}: {}",
+                    &manifest_path, e
+                );
+                false
+            }
+        }
+    }
+
     fn delete(&self, module_id: &str) -> bool {
         let path = std::path::Path::new(&self.dir).join(module_id);
         debug!("deleting module at {:?}", path);
@@ -153,6 +181,24 @@ impl Store for DiskStore {
 
         Some((mod_binary, env_vars, caps))
     }
+
+    fn retrieve_manifest(&self, module_id: &str) -> Option<Value> {
+        let path = std::path::Path::new(&self.dir)
+            .join(module_id)
+            .join("manifest.json");
+        debug!("resolving manifest at {:?}", path);
+
+        if !path.exists() {
+            return None;
+        }
+
+        or_warn(
+            std::fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string())),
+            "unable to load manifest",
+        )
+    }
 }
 
 fn or_warn<T, E>(res: Result<T, E>, prefix: &'static str) -> Option<T>
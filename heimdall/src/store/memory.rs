@@ -0,0 +1,138 @@
+use crate::store::Store;
+use log::{debug, error};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Keeps modules in memory, for registries that do not outlive the process, such as the ones
+/// bifrost builds to execute modules in-process.
+pub struct MemoryStore {
+    modules: Mutex<HashMap<String, Entry>>,
+}
+
+#[derive(Clone)]
+struct Entry {
+    binary: Vec<u8>,
+    variables: Vec<(String, String)>,
+    capabilities: HashMap<String, HashMap<String, String>>,
+    manifest: Option<Value>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            modules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn with_modules<T>(&self, f: impl FnOnce(&mut HashMap<String, Entry>) -> T) -> T {
+        match self.modules.lock() {
+            Ok(mut modules) => f(&mut modules),
+            Err(e) => f(&mut e.into_inner()),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Store for MemoryStore {
+    fn store(&self, module_id: &str, binary: Vec<u8>) -> bool {
+        debug!("storing module {} in memory", module_id);
+
+        self.with_modules(|modules| {
+            if modules.contains_key(module_id) {
+                error!("will not overwrite existing module {}", module_id);
+                return false;
+            }
+
+            modules.insert(
+                module_id.to_string(),
+                Entry {
+                    binary,
+                    variables: Vec::new(),
+                    capabilities: HashMap::new(),
+                    manifest: None,
+                },
+            );
+
+            true
+        })
+    }
+
+    fn attach_variables(&self, module_id: &str, variables: &Vec<(String, String)>) -> bool {
+        debug!("attaching env vars to module {} in memory", module_id);
+
+        self.with_modules(|modules| match modules.get_mut(module_id) {
+            Some(entry) => {
+                entry.variables = variables.clone();
+                true
+            }
+            None => {
+                error!("cannot attach env var to missing module {}", module_id);
+                false
+            }
+        })
+    }
+
+    fn attach_capabilities(
+        &self,
+        module_id: &str,
+        capabilities: &HashMap<String, HashMap<String, String>>,
+    ) -> bool {
+        debug!("attaching capabilities to module {} in memory", module_id);
+
+        self.with_modules(|modules| match modules.get_mut(module_id) {
+            Some(entry) => {
+                entry.capabilities = capabilities.clone();
+                true
+            }
+            None => {
+                error!("cannot attach capabilities to missing module {}", module_id);
+                false
+            }
+        })
+    }
+
+    fn attach_manifest(&self, module_id: &str, manifest: &Value) -> bool {
+        debug!("attaching manifest to module {} in memory", module_id);
+
+        self.with_modules(|modules| match modules.get_mut(module_id) {
+            Some(entry) => {
+                entry.manifest = Some(manifest.clone());
+                true
+            }
+            None => {
+                error!("cannot attach manifest to missing module {}", module_id);
+                false
+            }
+        })
+    }
+
+    fn delete(&self, module_id: &str) -> bool {
+        debug!("deleting module {} from memory", module_id);
+        self.with_modules(|modules| modules.remove(module_id).is_some())
+    }
+
+    fn retrieve(
+        &self,
+        module_id: &str,
+    ) -> Option<(
+        Vec<u8>,
+        Vec<(String, String)>,
+        HashMap<String, HashMap<String, String>>,
+    )> {
+        debug!("resolving module {} in memory", module_id);
+
+        self.with_modules(|modules| modules.get(module_id).cloned())
+            .map(|entry| (entry.binary, entry.variables, entry.capabilities))
+    }
+
+    fn retrieve_manifest(&self, module_id: &str) -> Option<Value> {
+        debug!("resolving manifest of module {} in memory", module_id);
+        self.with_modules(|modules| modules.get(module_id).and_then(|e| e.manifest.clone()))
+    }
+}